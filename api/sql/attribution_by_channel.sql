@@ -0,0 +1,12 @@
+-- orders and revenue of the attributed orders by their first-touch or last-touch channel
+-- @param touch first_touch_channel
+-- @param filters 1 = 1
+select
+    coalesce({touch}, 'none') as channel,
+    count(*) as orders,
+    coalesce(sum(amount), 0) as revenue,
+    avg(sessions_before_order) as average_sessions_before_order
+from webshop.order_attribution
+where {filters}
+group by channel
+order by orders desc, channel;
@@ -0,0 +1,6 @@
+-- copy data from the stage to the table
+COPY INTO webshop.events
+FROM (
+    SELECT *
+    FROM @sessionized
+);
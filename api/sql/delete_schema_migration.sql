@@ -0,0 +1,3 @@
+-- forget a reverted migration
+DELETE FROM webshop.schema_migrations
+WHERE version = {version};
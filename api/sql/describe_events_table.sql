@@ -0,0 +1,2 @@
+-- the columns of the events table, in table order
+DESC webshop.events;
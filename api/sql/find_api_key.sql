@@ -0,0 +1,9 @@
+-- the active api key with the given hash
+-- @param key_hash 0
+select
+    id,
+    name,
+    scopes
+from webshop.api_keys
+where key_hash = '{key_hash}' and revoked_at IS NULL
+limit 1;
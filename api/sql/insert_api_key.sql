@@ -0,0 +1,9 @@
+-- store a new api key
+INSERT INTO webshop.api_keys
+SELECT
+    '{id}',
+    '{name}',
+    '{key_hash}',
+    '{scopes}',
+    now(),
+    NULL;
@@ -0,0 +1,11 @@
+-- store one row of precomputed metrics
+INSERT INTO webshop.metrics_daily
+SELECT
+    today(),
+    {session_length},
+    {exclude_suspect},
+    {median_visits_before_order},
+    {median_session_duration_minutes_before_order},
+    now(),
+    '{conversion_types}',
+    '{visits_variant}';
@@ -0,0 +1,7 @@
+-- record an applied migration
+INSERT INTO webshop.schema_migrations
+SELECT
+    {version},
+    '{name}',
+    '{checksum}',
+    now();
@@ -0,0 +1,17 @@
+-- the most recently precomputed metrics of the sessions in the events table
+--
+-- every load and re-sessionization refreshes the metrics, so the newest row carries the session
+-- length the events are sessionized with and rows of other lengths are of older sessions
+-- @param exclude_suspect 0
+-- @param conversion_types placed_order
+-- @param visits_variant completed_cycles
+select
+    median_visits_before_order,
+    median_session_duration_minutes_before_order
+from webshop.metrics_daily
+where exclude_suspect = {exclude_suspect}
+  and conversion_types = '{conversion_types}'
+  and visits_variant = '{visits_variant}'
+  and session_length = (select session_length from webshop.metrics_daily order by refreshed_at desc limit 1)
+order by refreshed_at desc
+limit 1;
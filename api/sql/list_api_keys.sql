@@ -0,0 +1,9 @@
+-- every api key, revoked ones included
+select
+    id,
+    name,
+    scopes,
+    to_string(created_at) as created_at,
+    to_string(revoked_at) as revoked_at
+from webshop.api_keys
+order by created_at;
@@ -0,0 +1,8 @@
+-- the applied migrations
+select
+    version,
+    name,
+    checksum,
+    to_string(applied_at) as applied_at
+from webshop.schema_migrations
+order by version;
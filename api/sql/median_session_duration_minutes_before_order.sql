@@ -0,0 +1,60 @@
+-- median duration in minutes of the sessions before the first conversion
+-- @param events webshop.events
+-- @param conversion_types 'placed_order'
+
+-- identify the conversion events, placed orders unless other types are given
+with placed_order_events as (
+    select
+        *,
+        case
+            when type in ({conversion_types}) then 1
+            else 0
+        end as placed_order
+    from {events}
+),
+
+-- accumulate the orders
+order_numbers as (
+    select
+        *,
+        sum(placed_order) over(partition by customer_id order by timestamp) as order_number
+    from placed_order_events
+),
+
+-- get all events before the first order which also bring all sessions before first order
+events_before_first_order as (
+    select
+        *
+    from order_numbers
+    where order_number = 0
+),
+
+-- get duration of each session
+session_metrics as (
+    select
+        customer_id,
+        timestamp,
+        session_number,
+        min(timestamp) over(partition by customer_id, session_number) as min_session_event,
+        max(timestamp) over(partition by customer_id, session_number) as max_session_event
+    from events_before_first_order
+),
+
+-- calculate duration
+session_duration as (
+    select distinct
+        customer_id,
+        session_number,
+        ((max_session_event - min_session_event)/1000000)/60 as session_duration
+    from session_metrics
+),
+
+-- calculate median
+final as (
+    select
+        median(session_duration) as median_session_duration_minutes_before_order
+    from session_duration
+)
+
+select *
+from final;
@@ -0,0 +1,81 @@
+-- median number of sessions per conversion cycle of a customer
+--
+-- a cycle ending in a conversion counts the sessions before the conversion as defined in
+-- webshop-core's models, the same count the revenue and the attribution report. cycles are of
+-- three kinds:
+--   first:     the cycle ending in the first conversion of a customer
+--   completed: every later cycle ending in a conversion
+--   open:      the sessions after the last conversion, the whole history of customers without one
+-- the variants of the api select the kinds:
+--   first_order      'first'
+--   completed_cycles 'first', 'completed'
+--   all_cycles       'first', 'completed', 'open'
+-- @param events webshop.events
+-- @param conversion_types 'placed_order'
+-- @param cycles 'first', 'completed'
+
+-- the session of every conversion and of the conversion before it
+with conversions as (
+    select
+        customer_id,
+        session_number,
+        row_number() over(partition by customer_id order by timestamp) as conversion_number,
+        lag(session_number) over(partition by customer_id order by timestamp) as previous_session
+    from {events}
+    where type in ({conversion_types})
+),
+
+-- the cycles ending in a conversion
+closed_cycles as (
+    select
+        customer_id,
+        if(conversion_number = 1, 'first', 'completed') as kind,
+        greatest(session_number - coalesce(previous_session, -1), 1) as sessions
+    from conversions
+),
+
+-- the last session and the session of the last conversion of every customer
+last_sessions as (
+    select
+        customer_id,
+        max(session_number) as last_session
+    from {events}
+    group by customer_id
+),
+
+last_conversions as (
+    select
+        customer_id,
+        max(session_number) as last_conversion_session
+    from conversions
+    group by customer_id
+),
+
+-- the sessions after the last conversion, if there are any
+open_cycles as (
+    select
+        last_sessions.customer_id,
+        'open' as kind,
+        last_session - coalesce(last_conversion_session, -1) as sessions
+    from last_sessions
+    left join last_conversions on last_conversions.customer_id = last_sessions.customer_id
+    where last_session - coalesce(last_conversion_session, -1) > 0
+),
+
+cycles as (
+    select * from closed_cycles
+    union all
+    select * from open_cycles
+),
+
+-- median the result, 0 without any cycle of the selected kinds
+final as (
+    select
+        coalesce(median(sessions), 0) as median_visits_before_order
+    from cycles
+    where kind in ({cycles})
+)
+
+select
+    *
+from final;
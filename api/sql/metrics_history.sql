@@ -0,0 +1,17 @@
+-- every precomputed metrics row, by session length and oldest first within each
+-- @param exclude_suspect 0
+-- @param conversion_types placed_order
+-- @param visits_variant completed_cycles
+select
+    to_string(metric_date) as metric_date,
+    session_length,
+    conversion_types,
+    visits_variant,
+    median_visits_before_order,
+    median_session_duration_minutes_before_order,
+    to_string(refreshed_at) as refreshed_at
+from webshop.metrics_daily
+where exclude_suspect = {exclude_suspect}
+  and conversion_types = '{conversion_types}'
+  and visits_variant = '{visits_variant}'
+order by session_length, refreshed_at;
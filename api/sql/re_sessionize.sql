@@ -0,0 +1,81 @@
+-- sessionize the loaded events again with another session length
+--
+-- the sessions of `sessionize` in the etl: an event more than session_length minutes after the
+-- previous event of the customer starts a new session, a gap of exactly session_length doesn't.
+-- the gap is in fractional minutes from the timestamps in microseconds, time_diff keeps it in
+-- whole minutes like the etl loads it
+--
+-- events of a customer with the same timestamp keep their order: the first of them in the input
+-- of the etl carries the gap to the event before, the largest time_diff, the others are 0 minutes
+-- after it
+--
+-- with flag_suspects 1 the customers are flagged again on the new sessions like `flag_suspects`
+-- of the etl with the same thresholds, otherwise is_suspect is 0. the attributes are kept as loaded
+-- @param events webshop.events
+-- @param session_length 30
+-- @param flag_suspects 0
+-- @param max_events_per_minute 30
+-- @param min_median_gap_seconds 1
+-- @param max_session_events 1000
+INSERT OVERWRITE {events}
+
+-- number the events of every customer in order and take the gap to the previous one
+with gaps as (
+    select
+        customer_id,
+        timestamp,
+        type,
+        attributes,
+        row_number() over(partition by customer_id order by timestamp, time_diff desc) as event_number,
+        to_int64(timestamp) - lag(to_int64(timestamp)) over(partition by customer_id order by timestamp, time_diff desc) as gap_micros
+    from {events}
+),
+
+-- a gap of more than session_length minutes starts a new session
+new_sessions as (
+    select
+        *,
+        coalesce(gap_micros / 60000000, 0) as gap_minutes,
+        if(coalesce(gap_micros / 60000000, 0) > {session_length}, 1, 0) as new_session
+    from gaps
+),
+
+-- accumulate the new sessions of every customer in event order
+sessions as (
+    select
+        *,
+        sum(new_session) over(partition by customer_id order by event_number rows between unbounded preceding and current row) as session_number
+    from new_sessions
+),
+
+-- rate every session like the etl: its events per minute, sessions shorter than a minute as if
+-- they lasted one, and the median gap between its events, leaving out the gap before the first
+suspect_sessions as (
+    select
+        customer_id,
+        session_number,
+        if(
+            count(*) / greatest((to_int64(max(timestamp)) - to_int64(min(timestamp))) / 60000000, 1) > {max_events_per_minute}
+                or coalesce(median(if(new_session = 1, null, gap_micros / 1000000)) < {min_median_gap_seconds}, false)
+                or count(*) > {max_session_events},
+            1,
+            0
+        ) as suspect_session
+    from sessions
+    group by customer_id, session_number
+)
+
+-- a customer with any suspect session is flagged as a whole
+select
+    s.customer_id,
+    s.timestamp,
+    to_int64(floor(s.gap_minutes)) as time_diff,
+    s.new_session,
+    s.session_number,
+    s.type,
+    if({flag_suspects} = 1, max(suspect_sessions.suspect_session) over(partition by s.customer_id), 0) as is_suspect,
+    s.attributes
+from sessions s
+join suspect_sessions
+    on suspect_sessions.customer_id = s.customer_id
+    and suspect_sessions.session_number = s.session_number;
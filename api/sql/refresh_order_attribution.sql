@@ -0,0 +1,60 @@
+-- attribute every placed order to the sessions and channels of the journey leading to it
+--
+-- the journey of an order starts in the session after the session of the customer's previous
+-- order, so its sessions are the sessions before the order as the revenue and the median visits
+-- count them (see webshop-core's models). an order in the same session as the previous one has
+-- the events after the previous order as its journey. the channel of an event is its utm_source
+-- attribute, else its referrer
+-- @param amount amount
+INSERT OVERWRITE webshop.order_attribution
+SELECT
+    orders.customer_id,
+    orders.timestamp as order_timestamp,
+    orders.journey as order_number,
+    orders.session_number as converting_session,
+    orders.first_session,
+    orders.session_number - orders.first_session + 1 as sessions_before_order,
+    touches.first_touch_channel,
+    touches.last_touch_channel,
+    try_cast(orders.attributes['{amount}'] as double) as amount,
+    orders.is_suspect,
+    now() as refreshed_at
+FROM (
+    -- number the orders of every customer and find the first session of their journeys
+    SELECT
+        *,
+        row_number() over(partition by customer_id order by timestamp) as journey,
+        least(coalesce(lag(session_number) over(partition by customer_id order by timestamp), -1) + 1, session_number) as first_session
+    FROM webshop.events
+    WHERE type = 'placed_order'
+) orders
+LEFT JOIN (
+    -- the first and last channel seen in the journey
+    SELECT
+        events.customer_id,
+        events.journey,
+        arg_min(events.channel, events.timestamp) as first_touch_channel,
+        arg_max(events.channel, events.timestamp) as last_touch_channel
+    FROM (
+        -- the events of an order belong to its journey
+        SELECT
+            customer_id,
+            timestamp,
+            session_number,
+            coalesce(sum(if(type = 'placed_order', 1, 0)) over(partition by customer_id order by timestamp rows between unbounded preceding and 1 preceding), 0) + 1 as journey,
+            coalesce(try_cast(attributes['utm_source'] as string), try_cast(attributes['referrer'] as string)) as channel
+        FROM webshop.events
+    ) events
+    JOIN (
+        SELECT
+            customer_id,
+            row_number() over(partition by customer_id order by timestamp) as journey,
+            least(coalesce(lag(session_number) over(partition by customer_id order by timestamp), -1) + 1, session_number) as first_session
+        FROM webshop.events
+        WHERE type = 'placed_order'
+    ) journeys ON journeys.customer_id = events.customer_id AND journeys.journey = events.journey
+    -- browsing after the previous order in its session is part of no journey
+    WHERE events.channel IS NOT NULL
+        AND events.session_number >= journeys.first_session
+    GROUP BY events.customer_id, events.journey
+) touches ON touches.customer_id = orders.customer_id AND touches.journey = orders.journey;
@@ -0,0 +1,37 @@
+-- revenue of the placed orders by the number of sessions before the order, as defined in
+-- webshop-core's models and counted the same way by the attribution and the median visits
+-- @param events webshop.events
+-- @param amount amount
+
+-- the amount of every order and the session of the customer's previous order
+with orders as (
+    select
+        customer_id,
+        session_number,
+        try_cast(attributes['{amount}'] as double) as amount,
+        lag(session_number) over(partition by customer_id order by timestamp) as previous_order_session
+    from {events}
+    where type = 'placed_order'
+),
+
+-- sessions are numbered from 0
+sessions_per_order as (
+    select
+        customer_id,
+        greatest(session_number - coalesce(previous_order_session, -1), 1) as sessions_before_order,
+        amount
+    from orders
+)
+
+select
+    sessions_before_order,
+    count(*) as orders,
+    coalesce(sum(amount), 0) as revenue,
+    coalesce(avg(amount), 0) as average_order_value,
+    -- over the sessions the orders of the bucket took, at least one per order
+    coalesce(sum(amount), 0) / sum(sessions_before_order) as revenue_per_session,
+    -- over the customers with an order in the bucket
+    coalesce(sum(amount), 0) / count(distinct customer_id) as revenue_per_customer
+from sessions_per_order
+group by sessions_before_order
+order by sessions_before_order;
@@ -0,0 +1,44 @@
+-- revenue of the placed orders, the amount of an order is read from its attributes
+-- @param events webshop.events
+-- @param amount amount
+
+-- the amount of every order, null when the order has none
+with orders as (
+    select
+        try_cast(attributes['{amount}'] as double) as amount
+    from {events}
+    where type = 'placed_order'
+),
+
+order_totals as (
+    select
+        coalesce(sum(amount), 0) as total_revenue,
+        count(*) as orders,
+        count(amount) as orders_with_amount,
+        coalesce(avg(amount), 0) as average_order_value
+    from orders
+),
+
+-- every session and customer, ordering or not
+sessions as (
+    select distinct
+        customer_id, session_number
+    from {events}
+),
+
+visitor_totals as (
+    select
+        count(distinct customer_id) as customers,
+        count(*) as sessions
+    from sessions
+)
+
+select
+    total_revenue,
+    orders,
+    orders - orders_with_amount as orders_without_amount,
+    average_order_value,
+    if(sessions = 0, 0, total_revenue / sessions) as revenue_per_session,
+    if(customers = 0, 0, total_revenue / customers) as revenue_per_customer
+from order_totals
+cross join visitor_totals;
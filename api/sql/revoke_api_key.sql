@@ -0,0 +1,4 @@
+-- revoke an api key, it stops working once the api's key cache expires
+UPDATE webshop.api_keys
+SET revoked_at = now()
+WHERE id = '{id}' AND revoked_at IS NULL;
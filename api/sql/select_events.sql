@@ -0,0 +1,17 @@
+-- a page of the sessionized events
+-- @param filters 1 = 1
+-- @param limit 100
+-- @param offset 0
+select
+    customer_id,
+    to_string(timestamp) as timestamp,
+    time_diff,
+    new_session,
+    session_number,
+    type,
+    is_suspect,
+    to_string(attributes) as attributes
+from webshop.events
+where {filters}
+order by customer_id, timestamp
+limit {limit} offset {offset};
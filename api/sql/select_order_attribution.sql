@@ -0,0 +1,19 @@
+-- a page of the attributed orders
+-- @param filters 1 = 1
+-- @param limit 100
+-- @param offset 0
+select
+    customer_id,
+    to_string(order_timestamp) as order_timestamp,
+    order_number,
+    converting_session,
+    first_session,
+    sessions_before_order,
+    first_touch_channel,
+    last_touch_channel,
+    amount,
+    is_suspect
+from webshop.order_attribution
+where {filters}
+order by customer_id, order_timestamp
+limit {limit} offset {offset};
@@ -0,0 +1,3 @@
+-- check whether data has arrived in the stage
+-- @skip-validation
+select * from @sessionized limit 10;
@@ -0,0 +1,401 @@
+use anyhow::{bail, Result};
+use rocket::http::{HeaderMap, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::exporter::metrics;
+use crate::models::DbConnection;
+
+// what an api key is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    // read the order and declared metrics
+    MetricsRead,
+    // view the raw events, which expose customer ids
+    EventsRead,
+    // trigger a re-sessionization of the loaded events
+    SessionsWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::MetricsRead, Scope::EventsRead, Scope::SessionsWrite];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::MetricsRead => "metrics:read",
+            Scope::EventsRead => "events:read",
+            Scope::SessionsWrite => "sessions:write",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Result<Self> {
+        match Scope::ALL.iter().find(|s| s.as_str() == scope.trim()) {
+            Some(scope) => Ok(*scope),
+            None => bail!("unknown scope `{}`, expected one of metrics:read, events:read, sessions:write", scope),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// an active api key, as stored in webshop.api_keys
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    // comma separated scopes
+    pub scopes: String,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.split(',').any(|s| s.trim() == scope.as_str())
+    }
+}
+
+// an api key as listed by the admin cli
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: String,
+    pub created_at: String,
+    pub revoked_at: Option<String>,
+}
+
+// the scope a route requires, as a type so it can be a parameter of the `Authorized` guard
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+pub struct ReadMetrics;
+pub struct ReadEvents;
+pub struct WriteSessions;
+
+impl RequiredScope for ReadMetrics {
+    const SCOPE: Scope = Scope::MetricsRead;
+}
+
+impl RequiredScope for ReadEvents {
+    const SCOPE: Scope = Scope::EventsRead;
+}
+
+impl RequiredScope for WriteSessions {
+    const SCOPE: Scope = Scope::SessionsWrite;
+}
+
+// why a request was not authorized
+#[derive(Debug)]
+pub enum AuthError {
+    // no key in the Authorization or X-Api-Key header
+    Missing,
+    // the key is unknown or revoked
+    Invalid,
+    // the key lacks the scope of the route
+    Forbidden(Scope),
+    // the keys could not be looked up
+    Unavailable,
+}
+
+// request guard of a route that needs an api key with the scope `S`
+//
+// the key is sent as `Authorization: Bearer <key>` or `X-Api-Key: <key>`,
+// a missing or unknown key is answered with a 401, a key without the scope with a 403
+pub struct Authorized<S: RequiredScope> {
+    pub key: ApiKey,
+    scope: PhantomData<S>,
+}
+
+#[rocket::async_trait]
+impl<'r, S: RequiredScope> FromRequest<'r> for Authorized<S> {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(presented) = presented_key(request.headers()) else {
+            metrics().error("auth");
+            return Outcome::Failure((Status::Unauthorized, AuthError::Missing));
+        };
+
+        let (Some(db), Some(keys)) = (request.rocket().state::<DbConnection>(), request.rocket().state::<KeyStore>()) else {
+            error!("api keys are not configured");
+            return Outcome::Failure((Status::ServiceUnavailable, AuthError::Unavailable));
+        };
+
+        match keys.verify(db, presented).await {
+            Ok(key) => match authorize(key, S::SCOPE) {
+                Ok(key) => Outcome::Success(Authorized {
+                    key,
+                    scope: PhantomData,
+                }),
+                Err(failure) => {
+                    metrics().error("auth");
+                    Outcome::Failure(failure)
+                }
+            },
+            Err(e) => {
+                error!(error = format!("{:#}", e), "error looking up api key");
+                Outcome::Failure((Status::ServiceUnavailable, AuthError::Unavailable))
+            }
+        }
+    }
+}
+
+// the key of a request, from `Authorization: Bearer <key>` or else `X-Api-Key: <key>`
+fn presented_key<'r>(headers: &'r HeaderMap<'_>) -> Option<&'r str> {
+    headers
+        .get_one("Authorization")
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .or_else(|| headers.get_one("X-Api-Key"))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+// a verified key with the scope passes, an unknown or revoked one is a 401, one without the scope a 403
+fn authorize(key: Option<ApiKey>, scope: Scope) -> Result<ApiKey, (Status, AuthError)> {
+    match key {
+        Some(key) if key.has_scope(scope) => Ok(key),
+        Some(key) => {
+            warn!(key = %key.id, %scope, "api key lacks scope");
+            Err((Status::Forbidden, AuthError::Forbidden(scope)))
+        }
+        None => {
+            warn!("unknown or revoked api key");
+            Err((Status::Unauthorized, AuthError::Invalid))
+        }
+    }
+}
+
+// verifies presented keys against webshop.api_keys, remembering the outcome for a while
+// so not every request costs a query; a revoked key keeps working until its entry expires
+pub struct KeyStore {
+    ttl: Duration,
+    verified: Mutex<HashMap<String, (Option<ApiKey>, Instant)>>,
+}
+
+impl KeyStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn verify(&self, db: &DbConnection, presented: &str) -> Result<Option<ApiKey>> {
+        self.verify_with(presented, |key_hash| find_key(db, key_hash)).await
+    }
+
+    // verify with the given lookup of an active key by the hash of the presented one
+    async fn verify_with<F, Fut>(&self, presented: &str, lookup: F) -> Result<Option<ApiKey>>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<Option<ApiKey>>>,
+    {
+        let key_hash = hash_key(presented);
+
+        {
+            let verified = self.verified.lock().expect("lock verified keys");
+
+            if let Some((key, at)) = verified.get(&key_hash) {
+                if at.elapsed() < self.ttl {
+                    return Ok(key.clone());
+                }
+            }
+        }
+
+        let key = lookup(key_hash.clone()).await?;
+
+        let mut verified = self.verified.lock().expect("lock verified keys");
+
+        // made up keys would otherwise pile up forever
+        if verified.len() > 10_000 {
+            verified.retain(|_, (_, at)| at.elapsed() < self.ttl);
+        }
+        verified.insert(key_hash, (key.clone(), Instant::now()));
+
+        Ok(key)
+    }
+}
+
+// the active key with the given hash
+async fn find_key(db: &DbConnection, key_hash: String) -> Result<Option<ApiKey>> {
+    let sql = db.queries.render("find_api_key", &[("key_hash", key_hash)])?;
+
+    db.query_one("find_api_key", &sql).await
+}
+
+// create a key with the given scopes, the key itself is only returned here and never stored
+pub async fn create_key(db: &DbConnection, name: &str, scopes: &[Scope]) -> Result<(ApiKey, String)> {
+    if scopes.is_empty() {
+        bail!("an api key needs at least one scope");
+    }
+
+    let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+    let secret = format!("wsk_{}_{}{}", id, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let scopes = scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(",");
+
+    let sql = db.queries.render("insert_api_key", &[
+        ("id", id.clone()),
+        ("name", name.replace('\\', "\\\\").replace('\'', "''")),
+        ("key_hash", hash_key(&secret)),
+        ("scopes", scopes.clone()),
+    ])?;
+    db.pool.exec_once("insert_api_key", &sql).await?;
+
+    let key = ApiKey {
+        id,
+        name: name.to_string(),
+        scopes,
+    };
+
+    Ok((key, secret))
+}
+
+// revoke a key by id, false if there was no active key with that id
+pub async fn revoke_key(db: &DbConnection, id: &str) -> Result<bool> {
+    if !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("invalid api key id `{}`", id);
+    }
+
+    let sql = db.queries.render("revoke_api_key", &[("id", id.to_string())])?;
+
+    Ok(db.pool.exec_once("revoke_api_key", &sql).await? > 0)
+}
+
+pub async fn list_keys(db: &DbConnection) -> Result<Vec<ApiKeyInfo>> {
+    let sql = db.queries.render("list_api_keys", &[])?;
+
+    db.query_as("list_api_keys", &sql).await
+}
+
+pub(crate) fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn key(scopes: &str) -> ApiKey {
+        ApiKey {
+            id: "0123456789ab".to_string(),
+            name: "test".to_string(),
+            scopes: scopes.to_string(),
+        }
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap<'static> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.add(Header::new(*name, *value));
+        }
+
+        map
+    }
+
+    #[test]
+    fn keys_of_either_header() {
+        assert_eq!(presented_key(&headers(&[("Authorization", "Bearer wsk_1")])), Some("wsk_1"));
+        assert_eq!(presented_key(&headers(&[("X-Api-Key", " wsk_2 ")])), Some("wsk_2"));
+        // the bearer token wins
+        assert_eq!(presented_key(&headers(&[("Authorization", "Bearer wsk_1"), ("X-Api-Key", "wsk_2")])), Some("wsk_1"));
+        // another scheme falls back to X-Api-Key
+        assert_eq!(presented_key(&headers(&[("Authorization", "Basic dXNlcg=="), ("X-Api-Key", "wsk_2")])), Some("wsk_2"));
+        assert_eq!(presented_key(&headers(&[("Authorization", "Basic dXNlcg==")])), None);
+        assert_eq!(presented_key(&headers(&[("Authorization", "Bearer  ")])), None);
+        assert_eq!(presented_key(&headers(&[])), None);
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(Scope::parse(" events:read ").unwrap(), Scope::EventsRead);
+        assert!(Scope::parse("metrics:write").is_err());
+
+        let key = key("metrics:read, sessions:write");
+        assert!(key.has_scope(Scope::MetricsRead));
+        assert!(key.has_scope(Scope::SessionsWrite));
+        assert!(!key.has_scope(Scope::EventsRead));
+    }
+
+    #[test]
+    fn keys_without_the_scope_are_forbidden() {
+        assert_eq!(authorize(Some(key("metrics:read")), Scope::MetricsRead).unwrap().id, "0123456789ab");
+
+        let (status, error) = authorize(Some(key("metrics:read")), Scope::SessionsWrite).unwrap_err();
+        assert_eq!(status, Status::Forbidden);
+        assert!(matches!(error, AuthError::Forbidden(Scope::SessionsWrite)));
+    }
+
+    #[test]
+    fn unknown_or_revoked_keys_are_unauthorized() {
+        let (status, error) = authorize(None, Scope::MetricsRead).unwrap_err();
+
+        assert_eq!(status, Status::Unauthorized);
+        assert!(matches!(error, AuthError::Invalid));
+    }
+
+    // verify a key against a table holding `active`, counting the lookups
+    async fn verify(keys: &KeyStore, presented: &str, active: Option<ApiKey>, lookups: &AtomicUsize) -> Option<ApiKey> {
+        keys.verify_with(presented, |key_hash| async move {
+            assert_eq!(key_hash, hash_key(presented));
+            lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(active)
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn verified_keys_are_cached() {
+        let keys = KeyStore::new(Duration::from_secs(60));
+        let lookups = AtomicUsize::new(0);
+
+        assert!(verify(&keys, "wsk_1", Some(key("metrics:read")), &lookups).await.is_some());
+        // revoked meanwhile, the cached key still works
+        assert!(verify(&keys, "wsk_1", None, &lookups).await.is_some());
+        // unknown keys are cached too
+        assert!(verify(&keys, "wsk_2", None, &lookups).await.is_none());
+        assert!(verify(&keys, "wsk_2", Some(key("metrics:read")), &lookups).await.is_none());
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn revoked_keys_stop_working_when_their_entry_expires() {
+        let keys = KeyStore::new(Duration::from_millis(20));
+        let lookups = AtomicUsize::new(0);
+
+        assert!(verify(&keys, "wsk_1", Some(key("metrics:read")), &lookups).await.is_some());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(verify(&keys, "wsk_1", None, &lookups).await.is_none());
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_are_not_cached() {
+        let keys = KeyStore::new(Duration::from_secs(60));
+
+        let result = keys.verify_with("wsk_1", |_| async { Err(anyhow::anyhow!("error making connection")) }).await;
+        assert!(result.is_err());
+
+        let lookups = AtomicUsize::new(0);
+        assert!(verify(&keys, "wsk_1", Some(key("metrics:read")), &lookups).await.is_some());
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,8 +1,22 @@
+use clap::Parser;
 use std::io::Result;
 
+use webshop_config::{Component, Config, ConfigArgs};
+
+#[derive(Parser)]
+#[command(name = "api", about = "Serve the webshop metrics")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
 fn main() -> Result<()> {
+    // defaults < config file < env < command line, exits with every problem if incomplete
+    let cli = Cli::parse();
+    let config = Config::load_for(Component::Api, &cli.config);
+
     // boot up web server
-    api::router::rocket().expect("error launching api");
+    api::router::rocket(config).expect("error launching api");
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::time::Duration;
+
+use api::auth::{create_key, list_keys, revoke_key, Scope};
+use api::migrations;
+use api::models::DbConnection;
+use api::pool::PoolConfig;
+use api::queries::QueryRegistry;
+use webshop_config::{Component, Config, ConfigArgs};
+
+// manage the api keys stored in databend, connecting with the databend settings of the api
+#[derive(Parser)]
+#[command(name = "keys", about = "Create, revoke and list api keys")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a key with the given scopes, printed once and only stored as a hash
+    Create {
+        /// Who or what the key is for
+        #[arg(long)]
+        name: String,
+        /// metrics:read, events:read or sessions:write, repeat for several
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<String>,
+    },
+    /// Revoke a key by its id
+    Revoke { id: String },
+    /// List every key, revoked ones included
+    List,
+}
+
+#[rocket::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // settings for connecting to databend
+    let config = Config::load_for(Component::Cli, &cli.config);
+    let db = &config.databend;
+
+    let state = DbConnection::init(&db.user, &db.password, &db.host, &db.port, &db.database, PoolConfig::from(&db.pool), Duration::ZERO, QueryRegistry::embedded()).await?;
+
+    // the cli may run before the api prepared the database
+    migrations::up(&state, None).await?;
+
+    match cli.command {
+        Command::Create { name, scopes } => {
+            let scopes = scopes.iter().map(|s| Scope::parse(s)).collect::<Result<Vec<_>>>()?;
+            let (key, secret) = create_key(&state, &name, &scopes).await?;
+
+            println!("created key {} ({}) with scopes {}", key.id, key.name, key.scopes);
+            println!("{}", secret);
+            println!("store the key now, it can't be shown again");
+        }
+        Command::Revoke { id } => {
+            if revoke_key(&state, &id).await? {
+                println!("revoked key {}", id);
+            } else {
+                println!("no active key with id {}", id);
+            }
+        }
+        Command::List => {
+            for key in list_keys(&state).await? {
+                let status = match &key.revoked_at {
+                    Some(at) => format!("revoked {}", at),
+                    None => "active".to_string(),
+                };
+
+                println!("{}\t{}\t{}\t{}\t{}", key.id, key.name, key.scopes, key.created_at, status);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::time::Duration;
+
+use api::migrations;
+use api::models::DbConnection;
+use api::pool::PoolConfig;
+use api::queries::QueryRegistry;
+use webshop_config::{Component, Config, ConfigArgs};
+
+// apply and revert the schema migrations of the webshop database, connecting with the
+// databend settings of the api
+#[derive(Parser)]
+#[command(name = "migrate", about = "Apply, revert and list the schema migrations")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply the pending migrations
+    Up {
+        /// Stop after this version instead of applying every migration
+        #[arg(long)]
+        to: Option<u32>,
+    },
+    /// Revert the newest applied migrations
+    Down {
+        /// Number of migrations to revert
+        #[arg(long, default_value_t = 1, conflicts_with = "to")]
+        steps: usize,
+        /// Revert every migration above this version, 0 reverts all of them
+        #[arg(long)]
+        to: Option<u32>,
+    },
+    /// List every migration and whether it was applied
+    Status,
+}
+
+#[rocket::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // settings for connecting to databend
+    let config = Config::load_for(Component::Cli, &cli.config);
+    let db = &config.databend;
+
+    let state = DbConnection::init(&db.user, &db.password, &db.host, &db.port, &db.database, PoolConfig::from(&db.pool), Duration::ZERO, QueryRegistry::embedded()).await?;
+
+    match cli.command {
+        Command::Up { to } => {
+            let applied = migrations::up(&state, to).await?;
+
+            println!("applied {} migration(s)", applied.len());
+
+            // only the newest schema has to match the etl
+            if to.is_none() {
+                migrations::check_events_table(&state).await?;
+            }
+        }
+        Command::Down { steps, to } => {
+            let target = match to {
+                Some(to) => to,
+                None => migrations::version_before(&state, steps).await?,
+            };
+            let reverted = migrations::down(&state, target).await?;
+
+            println!("reverted {} migration(s)", reverted.len());
+        }
+        Command::Status => {
+            for m in migrations::status(&state).await? {
+                let status = match (&m.applied_at, m.modified, m.unknown) {
+                    (Some(_), _, true) => "applied, unknown to this build".to_string(),
+                    (Some(at), true, _) => format!("applied {}, changed since", at),
+                    (Some(at), false, _) => format!("applied {}", at),
+                    (None, _, _) => "pending".to_string(),
+                };
+
+                println!("{:04}\t{}\t{}", m.version, m.name, status);
+            }
+        }
+    }
+
+    Ok(())
+}
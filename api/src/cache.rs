@@ -0,0 +1,317 @@
+use anyhow::Result;
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::exporter::metrics;
+
+// above this many responses the expired ones are dropped, and the oldest ones if that isn't enough
+const MAX_ENTRIES: usize = 10_000;
+
+// serialized metrics response together with the etag of its body
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub body: String,
+    pub etag: String,
+}
+
+struct CacheEntry {
+    response: CachedResponse,
+    inserted: Instant,
+}
+
+// cache of serialized metrics responses keyed by the query parameters
+pub struct MetricsCache {
+    ttl: Duration,
+    // bumped on every invalidation so queries that started before a load don't store stale results
+    generation: AtomicU64,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl MetricsCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            generation: AtomicU64::new(0),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // get a response that has not yet expired
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        let entries = self.entries.lock().expect("lock metrics cache");
+
+        entries
+            .get(key)
+            .filter(|entry| entry.inserted.elapsed() < self.ttl)
+            .map(|entry| entry.response.clone())
+    }
+
+    // store a response computed during the given generation, unless the cache was invalidated meanwhile
+    pub fn insert(&self, key: &str, generation: u64, body: String) -> CachedResponse {
+        let response = CachedResponse {
+            etag: etag(&body),
+            body,
+        };
+
+        let mut entries = self.entries.lock().expect("lock metrics cache");
+
+        if generation == self.generation.load(Ordering::SeqCst) {
+            // every distinct filter value gets an entry, so a client cycling through them would
+            // otherwise grow the cache until the next load
+            if entries.len() >= MAX_ENTRIES && !entries.contains_key(key) {
+                entries.retain(|_, entry| entry.inserted.elapsed() < self.ttl);
+
+                while entries.len() >= MAX_ENTRIES {
+                    let oldest = entries
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.inserted)
+                        .map(|(key, _)| key.clone())
+                        .expect("cache is not empty");
+                    entries.remove(&oldest);
+                }
+            }
+
+            entries.insert(
+                key.to_string(),
+                CacheEntry {
+                    response: response.clone(),
+                    inserted: Instant::now(),
+                },
+            );
+        }
+
+        response
+    }
+
+    // serve the cached response for the key or compute, serialize and cache a new one
+    pub async fn get_or_insert_with<T, F, Fut>(&self, key: &str, compute: F) -> Result<CachedResponse>
+    where
+        T: Serialize,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        if let Some(response) = self.get(key) {
+            metrics().cache(true);
+            return Ok(response);
+        }
+
+        metrics().cache(false);
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let body = serde_json::to_string(&compute().await?)?;
+
+        Ok(self.insert(key, generation, body))
+    }
+
+    // drop every cached response, called whenever new data has been loaded
+    pub fn invalidate(&self) {
+        let mut entries = self.entries.lock().expect("lock metrics cache");
+
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.clear();
+    }
+}
+
+fn etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+
+    format!("\"{:x}\"", hasher.finish())
+}
+
+// the etags sent by the client in the If-None-Match header
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    pub fn matches(&self, etag: &str) -> bool {
+        match &self.0 {
+            Some(header) => header
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag),
+            None => false,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = request.headers().get_one("If-None-Match").map(str::to_string);
+
+        Outcome::Success(IfNoneMatch(header))
+    }
+}
+
+// JSON response that clients revalidate with their etag, answered with a 304 when unchanged
+pub enum CachedJson {
+    Fresh(CachedResponse),
+    NotModified(String),
+}
+
+impl CachedJson {
+    pub fn new(response: CachedResponse, if_none_match: &IfNoneMatch) -> Self {
+        if if_none_match.matches(&response.etag) {
+            CachedJson::NotModified(response.etag)
+        } else {
+            CachedJson::Fresh(response)
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for CachedJson {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            CachedJson::Fresh(response) => Response::build()
+                .header(ContentType::JSON)
+                .raw_header("ETag", response.etag)
+                .raw_header("Cache-Control", "no-cache")
+                .sized_body(response.body.len(), Cursor::new(response.body))
+                .ok(),
+            CachedJson::NotModified(etag) => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .raw_header("Cache-Control", "no-cache")
+                .ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn responses_are_served_from_the_cache() {
+        let cache = MetricsCache::new(TTL);
+
+        let first = cache.get_or_insert_with("key", || async { Ok(1) }).await.unwrap();
+        let second = cache.get_or_insert_with("key", || async { Ok(2) }).await.unwrap();
+
+        assert_eq!(first.body, "1");
+        assert_eq!(second.body, "1");
+        assert_eq!(first.etag, second.etag);
+    }
+
+    #[tokio::test]
+    async fn invalidate_drops_the_responses() {
+        let cache = MetricsCache::new(TTL);
+
+        cache.get_or_insert_with("key", || async { Ok(1) }).await.unwrap();
+        cache.invalidate();
+        let response = cache.get_or_insert_with("key", || async { Ok(2) }).await.unwrap();
+
+        assert_eq!(response.body, "2");
+        assert_ne!(response.etag, etag("1"));
+    }
+
+    #[test]
+    fn responses_read_before_an_invalidation_are_not_stored() {
+        let cache = MetricsCache::new(TTL);
+
+        // a query that started before new data was loaded
+        let generation = cache.generation.load(Ordering::SeqCst);
+        cache.invalidate();
+        let response = cache.insert("key", generation, "stale".to_string());
+
+        // the caller still gets its response, later ones don't
+        assert_eq!(response.body, "stale");
+        assert!(cache.get("key").is_none());
+    }
+
+    #[test]
+    fn responses_expire_after_the_ttl() {
+        let cache = MetricsCache::new(Duration::from_millis(20));
+
+        cache.insert("key", 0, "1".to_string());
+        assert!(cache.get("key").is_some());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get("key").is_none());
+    }
+
+    #[test]
+    fn the_oldest_response_is_evicted_when_full() {
+        let cache = MetricsCache::new(TTL);
+
+        cache.insert("oldest", 0, "0".to_string());
+        std::thread::sleep(Duration::from_millis(2));
+        for i in 1..MAX_ENTRIES {
+            cache.insert(&i.to_string(), 0, i.to_string());
+        }
+        // replacing a cached key doesn't evict
+        cache.insert("1", 0, "1".to_string());
+        assert!(cache.get("oldest").is_some());
+
+        cache.insert("newest", 0, "newest".to_string());
+
+        assert!(cache.get("oldest").is_none());
+        assert!(cache.get("newest").is_some());
+        assert_eq!(cache.entries.lock().unwrap().len(), MAX_ENTRIES);
+    }
+
+    #[test]
+    fn expired_responses_are_evicted_first() {
+        let cache = MetricsCache::new(Duration::from_millis(20));
+
+        for i in 0..MAX_ENTRIES {
+            cache.insert(&i.to_string(), 0, i.to_string());
+        }
+        std::thread::sleep(Duration::from_millis(30));
+        cache.insert("newest", 0, "newest".to_string());
+
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn if_none_match() {
+        let etag = etag("1");
+
+        assert!(!IfNoneMatch(None).matches(&etag));
+        assert!(IfNoneMatch(Some(etag.clone())).matches(&etag));
+        assert!(IfNoneMatch(Some(format!("\"other\", W/{etag}"))).matches(&etag));
+        assert!(IfNoneMatch(Some("*".to_string())).matches(&etag));
+        assert!(!IfNoneMatch(Some("\"other\"".to_string())).matches(&etag));
+    }
+
+    #[get("/cached")]
+    fn cached(if_none_match: IfNoneMatch) -> CachedJson {
+        let body = r#"{"visits":1}"#.to_string();
+
+        CachedJson::new(CachedResponse { etag: etag(&body), body }, &if_none_match)
+    }
+
+    #[test]
+    fn a_matching_etag_is_answered_with_304() {
+        let client = Client::tracked(rocket::build().mount("/", routes![cached])).unwrap();
+
+        let response = client.get("/cached").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(response.into_string().unwrap(), r#"{"visits":1}"#);
+
+        let response = client.get("/cached").header(Header::new("If-None-Match", etag.clone())).dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+        assert!(response.into_string().is_none());
+
+        let response = client.get("/cached").header(Header::new("If-None-Match", "\"other\"")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
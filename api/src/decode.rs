@@ -0,0 +1,66 @@
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use databend_driver::{Row, Value};
+
+// errors decoding a database row into a struct
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("column `{column}` is NULL but the field is not optional")]
+    Null { column: String },
+    #[error("column `{column}` has the wrong type: {message}")]
+    Type { column: String, message: String },
+    #[error("row has {values} values but the query returned {columns} columns")]
+    Shape { columns: usize, values: usize },
+    #[error("query failed: {0}")]
+    Query(String),
+}
+
+// convert a database value into JSON, keeping numbers and booleans typed
+pub fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Boolean(b) => serde_json::Value::from(*b),
+        Value::String(s) => serde_json::Value::from(s.clone()),
+        Value::Number(_) => {
+            let text = value.to_string();
+
+            text.parse::<i64>()
+                .map(serde_json::Value::from)
+                .or_else(|_| text.parse::<u64>().map(serde_json::Value::from))
+                .or_else(|_| text.parse::<f64>().map(serde_json::Value::from))
+                .unwrap_or(serde_json::Value::from(text))
+        }
+        // dates, timestamps and nested values are passed on in their text form
+        _ => serde_json::Value::from(value.to_string()),
+    }
+}
+
+// decode a row into a struct by matching the column names to the field names
+pub fn decode_row<T: DeserializeOwned>(columns: &[String], row: &Row) -> Result<T, DecodeError> {
+    let values = row.values();
+
+    if values.len() != columns.len() {
+        return Err(DecodeError::Shape {
+            columns: columns.len(),
+            values: values.len(),
+        });
+    }
+
+    let object: serde_json::Map<String, serde_json::Value> = columns
+        .iter()
+        .cloned()
+        .zip(values.iter().map(value_to_json))
+        .collect();
+
+    serde_path_to_error::deserialize(serde_json::Value::Object(object)).map_err(|e| {
+        let column = e.path().to_string();
+        let message = e.inner().to_string();
+
+        if message.starts_with("invalid type: null") {
+            DecodeError::Null { column }
+        } else {
+            DecodeError::Type { column, message }
+        }
+    })
+}
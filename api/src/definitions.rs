@@ -0,0 +1,114 @@
+use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::queries::{Query, QueryRegistry};
+
+// the models of the declared metrics, shared with the client
+pub use webshop_core::definitions::{FilterDefinition, MetricDefinition, MetricResult, OutputDefinition, ValueType};
+
+// the metric definitions compiled into the binary
+const EMBEDDED: &str = include_str!("../metrics.toml");
+
+// the static routes under /metrics, they win over /metrics/<name> so a metric named like one
+// could never be reached
+const RESERVED_NAMES: &[&str] = &["orders", "revenue", "attribution", "pool", "prometheus"];
+
+#[derive(Debug, Deserialize)]
+struct MetricsFile {
+    #[serde(default, rename = "metric")]
+    metrics: Vec<MetricDefinition>,
+}
+
+// the declared metrics, looked up by name
+#[derive(Debug, Clone)]
+pub struct MetricDefinitions {
+    metrics: Vec<MetricDefinition>,
+}
+
+impl MetricDefinitions {
+    pub fn parse(toml: &str) -> Result<Self> {
+        let file: MetricsFile = toml::from_str(toml).context("error parsing metric definitions")?;
+
+        for metric in &file.metrics {
+            metric.check()?;
+
+            if RESERVED_NAMES.contains(&metric.name.as_str()) {
+                bail!("metric `{}` has the name of a built-in route under /metrics", metric.name);
+            }
+        }
+
+        Ok(Self { metrics: file.metrics })
+    }
+
+    // the definitions compiled into the binary
+    pub fn embedded() -> Self {
+        Self::parse(EMBEDDED).expect("error parsing embedded metric definitions")
+    }
+
+    // definitions from a config file, replacing the embedded ones
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = fs::read_to_string(path).with_context(|| format!("error reading metric definitions {}", path.display()))?;
+
+        Self::parse(&toml)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MetricDefinition> {
+        self.metrics.iter().find(|m| m.name == name)
+    }
+
+    pub fn all(&self) -> &[MetricDefinition] {
+        &self.metrics
+    }
+
+    // add the inline sql of every metric to the registry so it is validated with the other queries
+    pub fn register(&self, mut queries: QueryRegistry) -> Result<QueryRegistry> {
+        for metric in &self.metrics {
+            match metric.inline_sql() {
+                Some(sql) => {
+                    let mut query = Query::parse(&metric.query_name(), &sql);
+                    query.defaults.entry("events".to_string()).or_insert_with(|| "webshop.events".to_string());
+                    query.defaults.entry("filters".to_string()).or_insert_with(|| "1 = 1".to_string());
+
+                    queries.insert(query);
+                }
+                None => {
+                    // every metric query is rendered with the filters, even without any declared
+                    let query = queries.get(&metric.query_name())?;
+                    ensure!(query.accepts("filters"), "query `{}` of metric `{}` has no {{filters}} placeholder", query.name, metric.name);
+                }
+            }
+        }
+
+        Ok(queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_names_are_rejected() {
+        let toml = r#"
+            [[metric]]
+            name = "orders"
+            aggregate = "count(*)"
+
+            [[metric.output]]
+            name = "events"
+            type = "int"
+        "#;
+
+        let error = MetricDefinitions::parse(toml).unwrap_err();
+        assert_eq!(error.to_string(), "metric `orders` has the name of a built-in route under /metrics");
+
+        assert!(MetricDefinitions::parse(&toml.replace("orders", "order_count")).is_ok());
+    }
+
+    #[test]
+    fn embedded_definitions_parse() {
+        assert!(!MetricDefinitions::embedded().all().is_empty());
+    }
+}
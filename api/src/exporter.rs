@@ -0,0 +1,118 @@
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::pool::PoolStats;
+
+static METRICS: OnceLock<ApiMetrics> = OnceLock::new();
+
+// prometheus metrics of the api, served at /metrics/prometheus
+pub struct ApiMetrics {
+    registry: Registry,
+    request_duration: HistogramVec,
+    query_duration: HistogramVec,
+    errors: IntCounterVec,
+    cache_requests: IntCounterVec,
+    pool: IntGaugeVec,
+    last_load: Gauge,
+}
+
+// the metrics shared by every part of the api
+pub fn metrics() -> &'static ApiMetrics {
+    METRICS.get_or_init(ApiMetrics::new)
+}
+
+impl ApiMetrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("api_request_duration_seconds", "Latency of http requests"),
+            &["method", "route", "status"],
+        )
+        .expect("error creating request duration histogram");
+        let query_duration = HistogramVec::new(
+            HistogramOpts::new("api_databend_query_duration_seconds", "Duration of databend queries"),
+            &["query", "outcome"],
+        )
+        .expect("error creating query duration histogram");
+        let errors = IntCounterVec::new(Opts::new("api_errors_total", "Errors by kind"), &["kind"])
+            .expect("error creating error counter");
+        let cache_requests = IntCounterVec::new(
+            Opts::new("api_cache_requests_total", "Metrics cache lookups by result"),
+            &["result"],
+        )
+        .expect("error creating cache counter");
+        let pool = IntGaugeVec::new(Opts::new("api_databend_pool_connections", "Connections of the pool by state"), &["state"])
+            .expect("error creating pool gauge");
+        let last_load = Gauge::new("api_last_load_timestamp_seconds", "Unix time the last load into webshop.events finished")
+            .expect("error creating last load gauge");
+
+        registry.register(Box::new(request_duration.clone())).expect("error registering request duration");
+        registry.register(Box::new(query_duration.clone())).expect("error registering query duration");
+        registry.register(Box::new(errors.clone())).expect("error registering errors");
+        registry.register(Box::new(cache_requests.clone())).expect("error registering cache requests");
+        registry.register(Box::new(pool.clone())).expect("error registering pool");
+        registry.register(Box::new(last_load.clone())).expect("error registering last load");
+
+        Self {
+            registry,
+            request_duration,
+            query_duration,
+            errors,
+            cache_requests,
+            pool,
+            last_load,
+        }
+    }
+
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        self.request_duration
+            .with_label_values(&[method, route, &status.to_string()])
+            .observe(latency.as_secs_f64());
+
+        if status >= 500 {
+            self.error("http");
+        }
+    }
+
+    pub fn observe_query(&self, query: &str, ok: bool, duration: Duration) {
+        let outcome = if ok { "ok" } else { "error" };
+
+        self.query_duration
+            .with_label_values(&[query, outcome])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn error(&self, kind: &str) {
+        self.errors.with_label_values(&[kind]).inc();
+    }
+
+    pub fn cache(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+
+        self.cache_requests.with_label_values(&[result]).inc();
+    }
+
+    pub fn loaded(&self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        self.last_load.set(now.as_secs_f64());
+    }
+
+    // the metrics in the prometheus text format, with the pool gauges taken at scrape time
+    pub fn render(&self, pool: &PoolStats) -> String {
+        self.pool.with_label_values(&["in_use"]).set(pool.in_use as i64);
+        self.pool.with_label_values(&["idle"]).set(pool.idle as i64);
+        self.pool.with_label_values(&["size"]).set(pool.size as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("error encoding prometheus metrics");
+
+        String::from_utf8(buffer).expect("prometheus metrics are not utf-8")
+    }
+}
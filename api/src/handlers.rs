@@ -1,17 +1,38 @@
 use anyhow::Result;
-use rocket::{http::Status, serde::json::Json, State};
+use rocket::{http::{ContentType, Status}, serde::json::Json, State};
+use std::collections::HashMap;
+use tracing::{error, instrument};
 
-use crate::models::{Message, Metrics, DbConnection};
+use crate::auth::{Authorized, ReadEvents, ReadMetrics, WriteSessions};
+use crate::cache::{CachedJson, IfNoneMatch};
+use crate::definitions::{MetricDefinition, MetricDefinitions, MetricResult};
+use crate::exporter::metrics;
+use crate::models::{AttributionMetrics, ConversionTypes, Event, LEGACY_VISITS_VARIANT, Message, Metrics, MetricsSnapshot, DbConnection, OrderAttribution, RevenueMetrics, VisitsVariant};
+use crate::pool::PoolStats;
+use crate::request_log::RequestId;
 
 pub type DbConn = State<DbConnection>;
+pub type Definitions = State<MetricDefinitions>;
 
-// basic index route
+/// basic index route
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "health",
+    responses((status = 200, description = "greeting", body = String, content_type = "text/plain"))
+)]
 #[get("/")]
 pub async fn index() -> &'static str {
     "Hello, world!"
 }
 
-// basic route to show JSON response
+/// basic route to show JSON response
+#[utoipa::path(
+    get,
+    path = "/ping",
+    tag = "health",
+    responses((status = 200, description = "pong", body = Message))
+)]
 #[get("/ping")]
 pub async fn ping() -> Result<Json<Message>, Status> {
     let result = Message::message("pong".to_string()).await;
@@ -22,70 +43,447 @@ pub async fn ping() -> Result<Json<Message>, Status> {
     }
 }
 
-// get metrics of the orders
-#[get("/orders")]
-pub async fn order_metrics(dbconn: &DbConn) -> Result<Json<Metrics>, Status> {
+/// get metrics of the orders, or of other conversion events, optionally excluding customers flagged as suspect
+#[utoipa::path(
+    get,
+    path = "/metrics/orders",
+    security(("api_key" = ["metrics:read"])),
+    tag = "metrics",
+    params(
+        ("exclude_suspect" = Option<bool>, Query, description = "leave out customers flagged as suspect, defaults to false"),
+        ("conversion" = Option<String>, Query, description = "comma separated event types counted as a conversion, defaults to the configured ones"),
+        ("variant" = Option<String>, Query, description = "cycles median_visits_before_order is the median of: completed_cycles (default), first_order or all_cycles"),
+        ("If-None-Match" = Option<String>, Header, description = "etag of a previous response"),
+    ),
+    responses(
+        (status = 200, description = "metrics of the orders", body = Metrics),
+        (status = 304, description = "the metrics did not change since the given etag"),
+        (status = 400, description = "an invalid conversion event type or variant"),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
+        (status = 500, description = "the metrics could not be computed"),
+    )
+)]
+#[get("/orders?<exclude_suspect>&<conversion>&<variant>")]
+#[instrument(name = "order_metrics", skip_all, fields(request_id = %request_id.0))]
+pub async fn order_metrics(
+    _auth: Authorized<ReadMetrics>,
+    dbconn: &DbConn,
+    request_id: RequestId,
+    if_none_match: IfNoneMatch,
+    exclude_suspect: Option<bool>,
+    conversion: Option<&str>,
+    variant: Option<&str>,
+) -> Result<CachedJson, Status> {
+    // handle the query params
+    let exclude_suspect = exclude_suspect.unwrap_or(false);
+    let conversion = conversion_types(dbconn, conversion)?;
+    let variant = visits_variant(variant)?;
+
+    // serve the metrics from the cache, computing them when missing or expired
+    let key = format!("orders?exclude_suspect={exclude_suspect}&conversion={}&variant={}", conversion.key(), variant.as_str());
+    let result = dbconn
+        .cache
+        .get_or_insert_with(&key, || dbconn.publish_metrics(exclude_suspect, &conversion, variant))
+        .await;
+
+    // returned serialized JSON metrics, or a 304 when the client already has them
+    match result {
+        Ok(result) => Ok(CachedJson::new(result, &if_none_match)),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error getting order metrics");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// get the history of the precomputed order metrics
+#[utoipa::path(
+    get,
+    path = "/metrics/orders/history",
+    security(("api_key" = ["metrics:read"])),
+    tag = "metrics",
+    params(
+        ("exclude_suspect" = Option<bool>, Query, description = "leave out customers flagged as suspect, defaults to false"),
+        ("conversion" = Option<String>, Query, description = "comma separated event types counted as a conversion, defaults to the configured ones"),
+        ("variant" = Option<String>, Query, description = "cycles median_visits_before_order is the median of: completed_cycles (default), first_order or all_cycles, or legacy for the rows precomputed before the variants"),
+    ),
+    responses(
+        (status = 200, description = "one row per refresh, by session length and oldest first within each", body = [MetricsSnapshot]),
+        (status = 400, description = "an invalid conversion event type or variant"),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
+        (status = 500, description = "the history could not be read"),
+    )
+)]
+#[get("/orders/history?<exclude_suspect>&<conversion>&<variant>")]
+#[instrument(name = "order_metrics_history", skip_all, fields(request_id = %request_id.0))]
+pub async fn order_metrics_history(
+    _auth: Authorized<ReadMetrics>,
+    dbconn: &DbConn,
+    request_id: RequestId,
+    exclude_suspect: Option<bool>,
+    conversion: Option<&str>,
+    variant: Option<&str>,
+) -> Result<Json<Vec<MetricsSnapshot>>, Status> {
+    // handle the query params
+    let exclude_suspect = exclude_suspect.unwrap_or(false);
+    let conversion = conversion_types(dbconn, conversion)?;
+    // the rows from before the variants are only in the history
+    let variant = match variant {
+        Some(variant) if variant.trim() == LEGACY_VISITS_VARIANT => LEGACY_VISITS_VARIANT,
+        variant => visits_variant(variant)?.as_str(),
+    };
+
+    // returned deserialized JSON metrics history
+    match dbconn.metrics_history(exclude_suspect, &conversion, variant).await {
+        Ok(result) => Ok(Json(result)),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error getting metrics history");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// get the revenue of the orders, broken down by the number of sessions before the order
+#[utoipa::path(
+    get,
+    path = "/metrics/revenue",
+    security(("api_key" = ["metrics:read"])),
+    tag = "metrics",
+    params(
+        ("exclude_suspect" = Option<bool>, Query, description = "leave out customers flagged as suspect, defaults to false"),
+        ("If-None-Match" = Option<String>, Header, description = "etag of a previous response"),
+    ),
+    responses(
+        (status = 200, description = "revenue of the orders", body = RevenueMetrics),
+        (status = 304, description = "the revenue did not change since the given etag"),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
+        (status = 500, description = "the revenue could not be computed"),
+    )
+)]
+#[get("/revenue?<exclude_suspect>")]
+#[instrument(name = "revenue_metrics", skip_all, fields(request_id = %request_id.0))]
+pub async fn revenue_metrics(
+    _auth: Authorized<ReadMetrics>,
+    dbconn: &DbConn,
+    request_id: RequestId,
+    if_none_match: IfNoneMatch,
+    exclude_suspect: Option<bool>,
+) -> Result<CachedJson, Status> {
+    // handle the query param
+    let exclude_suspect = exclude_suspect.unwrap_or(false);
+
+    // serve the revenue from the cache, computing it when missing or expired
+    let key = format!("revenue?exclude_suspect={exclude_suspect}");
+    let result = dbconn
+        .cache
+        .get_or_insert_with(&key, || dbconn.compute_revenue(exclude_suspect))
+        .await;
+
+    match result {
+        Ok(result) => Ok(CachedJson::new(result, &if_none_match)),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error getting revenue metrics");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// get the orders and revenue by the first-touch and last-touch channel of the journey leading to the order
+#[utoipa::path(
+    get,
+    path = "/metrics/attribution",
+    security(("api_key" = ["metrics:read"])),
+    tag = "metrics",
+    params(
+        ("exclude_suspect" = Option<bool>, Query, description = "leave out customers flagged as suspect, defaults to false"),
+        ("If-None-Match" = Option<String>, Header, description = "etag of a previous response"),
+    ),
+    responses(
+        (status = 200, description = "attributed orders by channel", body = AttributionMetrics),
+        (status = 304, description = "the attribution did not change since the given etag"),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
+        (status = 500, description = "the attribution could not be read"),
+    )
+)]
+#[get("/attribution?<exclude_suspect>")]
+#[instrument(name = "attribution_metrics", skip_all, fields(request_id = %request_id.0))]
+pub async fn attribution_metrics(
+    _auth: Authorized<ReadMetrics>,
+    dbconn: &DbConn,
+    request_id: RequestId,
+    if_none_match: IfNoneMatch,
+    exclude_suspect: Option<bool>,
+) -> Result<CachedJson, Status> {
+    // handle the query param
+    let exclude_suspect = exclude_suspect.unwrap_or(false);
+
+    // serve the attribution from the cache, reading it when missing or expired
+    let key = format!("attribution?exclude_suspect={exclude_suspect}");
+    let result = dbconn
+        .cache
+        .get_or_insert_with(&key, || dbconn.attribution_metrics(exclude_suspect))
+        .await;
+
+    match result {
+        Ok(result) => Ok(CachedJson::new(result, &if_none_match)),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error getting attribution metrics");
+            Err(Status::InternalServerError)
+        }
+    }
+}
 
-    // returned deserialized JSON metrics
-    Ok(
-        Json(
-            dbconn.publish_metrics().await
-        )
+/// get the usage of the database connection pool
+#[utoipa::path(
+    get,
+    path = "/metrics/pool",
+    security(("api_key" = ["metrics:read"])),
+    tag = "operations",
+    responses(
+        (status = 200, description = "usage of the pool since startup", body = PoolStats),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
     )
+)]
+#[get("/pool")]
+pub async fn pool_metrics(_auth: Authorized<ReadMetrics>, dbconn: &DbConn) -> Json<PoolStats> {
+    Json(dbconn.pool.stats())
 }
 
-// view the data the sessionized data
-// #[get("/view?<sessionized>&<side>&<nrow>")]
-// pub async fn view_data(
-//     data: &State<Data>,
-//     sessionized: Option<bool>,
-//     side: Option<&str>,
-//     nrow: Option<u32>,
-// ) -> Result<Json<DataView>, Status> {
-//     // handle the query params
-//     let sessionized = sessionized.unwrap_or(false);
-//     let side = side.unwrap_or("top");
-//     let nrow = nrow.unwrap_or(5);
-
-//     // process data view
-//     let result = data.view_data(sessionized, side, nrow).await;
-
-//     // on success deserialize object into JSON
-//     match result {
-//         Ok(result) => Ok(Json(result)),
-//         _ => Err(Status::NotFound),
-//     }
-// }
-
-// re-sessionized the data if you want to view other session_lengths without having to re-run
-// #[get("/re-sessionize?<session_length>")]
-// pub async fn re_sessionize(
-//     data: &State<Data>,
-//     session_length: Option<u32>,
-// ) -> Result<Json<Message>, Status> {
-//     // handle query param
-//     let session_length = session_length.unwrap_or(30);
-
-//     // process re-sessionization
-//     let result = data.re_sessionize(session_length).await;
-
-//     // on success, update state and send out success message of re-sessionization
-//     match result {
-//         Ok(result) => {
-//             // successful re-sessionization should edit state
-//             let mut lock = data.sessionizedf.lock().expect("lock shared data");
-//             *lock = result;
-
-//             let message = format!(
-//                 "Successfully resessionized the data with a session length of {}",
-//                 session_length
-//             );
-
-//             // return a success message
-//             Ok(Json(Message { message }))
-//         }
-//         _ => Err(Status::NotFound),
-//     }
-// }
+/// get the api metrics in the prometheus text format
+///
+/// like every route under /metrics it needs a key with the metrics:read scope, the scraper sends one
+/// with `authorization: { credentials: <key> }` in its scrape config
+#[utoipa::path(
+    get,
+    path = "/metrics/prometheus",
+    security(("api_key" = ["metrics:read"])),
+    tag = "operations",
+    responses(
+        (status = 200, description = "prometheus text exposition format", body = String, content_type = "text/plain"),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
+    )
+)]
+#[get("/prometheus")]
+pub async fn prometheus_metrics(_auth: Authorized<ReadMetrics>, dbconn: &DbConn) -> (ContentType, String) {
+    (ContentType::Plain, metrics().render(&dbconn.pool.stats()))
+}
 
+/// list the declared metrics
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    security(("api_key" = ["metrics:read"])),
+    tag = "metrics",
+    responses(
+        (status = 200, description = "the metrics served at /metrics/{name}", body = [MetricDefinition]),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
+    )
+)]
+#[get("/")]
+pub async fn list_metrics(_auth: Authorized<ReadMetrics>, definitions: &Definitions) -> Json<Vec<MetricDefinition>> {
+    Json(definitions.all().to_vec())
+}
 
+/// get a metric declared in the metric definitions, filtered by the query params it allows
+#[utoipa::path(
+    get,
+    path = "/metrics/{name}",
+    security(("api_key" = ["metrics:read"])),
+    tag = "metrics",
+    params(
+        ("name" = String, Path, description = "name of a declared metric"),
+        ("filters" = Option<Object>, Query, style = Form, explode, description = "the filters declared by the metric, e.g. customer_id=1"),
+        ("If-None-Match" = Option<String>, Header, description = "etag of a previous response"),
+    ),
+    responses(
+        (status = 200, description = "rows of the metric", body = MetricResult),
+        (status = 304, description = "the metric did not change since the given etag"),
+        (status = 400, description = "a filter is not declared by the metric or has the wrong type"),
+        (status = 404, description = "no metric with that name"),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the metrics:read scope"),
+        (status = 500, description = "the metric could not be computed"),
+    )
+)]
+#[get("/<name>?<filters..>")]
+#[instrument(name = "declared_metrics", skip_all, fields(request_id = %request_id.0, metric = name))]
+pub async fn declared_metrics(
+    _auth: Authorized<ReadMetrics>,
+    dbconn: &DbConn,
+    definitions: &Definitions,
+    request_id: RequestId,
+    if_none_match: IfNoneMatch,
+    name: &str,
+    filters: HashMap<String, String>,
+) -> Result<CachedJson, Status> {
+    let metric = definitions.get(name).ok_or(Status::NotFound)?;
+
+    // reject filters the metric doesn't declare or values of the wrong type
+    metric.where_clause(&filters).map_err(|_| Status::BadRequest)?;
+
+    // cache per metric and filter combination
+    let mut keys: Vec<String> = filters.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    keys.sort();
+    let key = format!("{}?{}", name, keys.join("&"));
+
+    let result = dbconn
+        .cache
+        .get_or_insert_with(&key, || dbconn.query_metric(metric, &filters))
+        .await;
+
+    match result {
+        Ok(result) => Ok(CachedJson::new(result, &if_none_match)),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error getting declared metric");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// view a page of the sessionized events, which expose customer ids
+#[utoipa::path(
+    get,
+    path = "/data/events",
+    security(("api_key" = ["events:read"])),
+    tag = "data",
+    params(
+        ("customer_id" = Option<i64>, Query, description = "only the events of this customer"),
+        ("limit" = Option<u32>, Query, description = "events per page, defaults to 100, at most 1000"),
+        ("offset" = Option<u32>, Query, description = "events to skip, defaults to 0"),
+    ),
+    responses(
+        (status = 200, description = "events ordered by customer and timestamp", body = [Event]),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the events:read scope"),
+        (status = 500, description = "the events could not be read"),
+    )
+)]
+#[get("/events?<customer_id>&<limit>&<offset>")]
+#[instrument(name = "view_events", skip_all, fields(request_id = %request_id.0, key = %auth.key.id))]
+pub async fn view_events(
+    auth: Authorized<ReadEvents>,
+    dbconn: &DbConn,
+    request_id: RequestId,
+    customer_id: Option<i64>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<Json<Vec<Event>>, Status> {
+    // handle the query params
+    let limit = limit.unwrap_or(100).min(1000);
+    let offset = offset.unwrap_or(0);
+
+    match dbconn.view_events(customer_id, limit, offset).await {
+        Ok(result) => Ok(Json(result)),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error viewing events");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// view a page of the attributed orders, which expose customer ids
+#[utoipa::path(
+    get,
+    path = "/data/attribution",
+    security(("api_key" = ["events:read"])),
+    tag = "data",
+    params(
+        ("customer_id" = Option<i64>, Query, description = "only the orders of this customer"),
+        ("limit" = Option<u32>, Query, description = "orders per page, defaults to 100, at most 1000"),
+        ("offset" = Option<u32>, Query, description = "orders to skip, defaults to 0"),
+    ),
+    responses(
+        (status = 200, description = "attributed orders ordered by customer and timestamp", body = [OrderAttribution]),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the events:read scope"),
+        (status = 500, description = "the attribution could not be read"),
+    )
+)]
+#[get("/attribution?<customer_id>&<limit>&<offset>")]
+#[instrument(name = "view_attribution", skip_all, fields(request_id = %request_id.0, key = %auth.key.id))]
+pub async fn view_attribution(
+    auth: Authorized<ReadEvents>,
+    dbconn: &DbConn,
+    request_id: RequestId,
+    customer_id: Option<i64>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<Json<Vec<OrderAttribution>>, Status> {
+    // handle the query params
+    let limit = limit.unwrap_or(100).min(1000);
+    let offset = offset.unwrap_or(0);
+
+    match dbconn.view_attribution(customer_id, limit, offset).await {
+        Ok(result) => Ok(Json(result)),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error viewing attribution");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// re-sessionize the loaded events to view the metrics of another session length without re-running the etl
+#[utoipa::path(
+    post,
+    path = "/data/re-sessionize",
+    security(("api_key" = ["sessions:write"])),
+    tag = "data",
+    params(
+        ("session_length" = Option<u32>, Query, description = "minutes of inactivity that end a session, defaults to 30"),
+    ),
+    responses(
+        (status = 200, description = "the events were re-sessionized and the metrics and attribution refreshed", body = Message),
+        (status = 401, description = "no api key or an unknown one"),
+        (status = 403, description = "the api key lacks the sessions:write scope"),
+        (status = 500, description = "the events could not be re-sessionized"),
+    )
+)]
+#[post("/re-sessionize?<session_length>")]
+#[instrument(name = "re_sessionize", skip_all, fields(request_id = %request_id.0, key = %auth.key.id))]
+pub async fn re_sessionize(
+    auth: Authorized<WriteSessions>,
+    dbconn: &DbConn,
+    request_id: RequestId,
+    session_length: Option<u32>,
+) -> Result<Json<Message>, Status> {
+    // handle query param
+    let session_length = session_length.unwrap_or(30);
+
+    match dbconn.re_sessionize(session_length).await {
+        Ok(()) => {
+            let message = format!(
+                "Successfully resessionized the data with a session length of {}",
+                session_length
+            );
+
+            Ok(Json(Message { message }))
+        }
+        Err(e) => {
+            error!(error = format!("{:#}", e), "error re-sessionizing");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+// the conversion event types of a request, the configured ones when not given
+fn conversion_types(dbconn: &DbConn, conversion: Option<&str>) -> Result<ConversionTypes, Status> {
+    match conversion {
+        Some(list) => ConversionTypes::parse(list).map_err(|_| Status::BadRequest),
+        None => Ok(dbconn.conversion.clone()),
+    }
+}
+
+// the variant of median_visits_before_order of a request, completed cycles when not given
+fn visits_variant(variant: Option<&str>) -> Result<VisitsVariant, Status> {
+    match variant {
+        Some(variant) => VisitsVariant::parse(variant).map_err(|_| Status::BadRequest),
+        None => Ok(VisitsVariant::default()),
+    }
+}
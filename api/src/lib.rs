@@ -1,6 +1,18 @@
 #[macro_use]
 extern crate rocket;
 
+pub mod auth;
+pub mod cache;
+pub mod decode;
+pub mod definitions;
+pub mod exporter;
 mod handlers;
+pub mod migrations;
 pub mod router;
 pub mod models;
+pub mod openapi;
+pub mod pool;
+pub mod queries;
+pub mod rate_limit;
+pub mod request_log;
+pub use webshop_config::secrets;
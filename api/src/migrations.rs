@@ -0,0 +1,209 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::models::DbConnection;
+use webshop_core::migrations::{self as embedded, Migration, MIGRATIONS};
+use webshop_core::schema::EVENT_COLUMNS;
+
+// runs the migrations of webshop-core against databend and records them in
+// webshop.schema_migrations
+//
+// databend can't roll back DDL, so a migration failing halfway leaves the statements before
+// the failing one applied and the migration unrecorded; the error says how far it got
+
+// a migration recorded in webshop.schema_migrations
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+// a migration known to this build or recorded in the database, and whether it was applied
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: String,
+    pub applied_at: Option<String>,
+    // the up file changed since the migration was applied
+    pub modified: bool,
+    // recorded in the database but unknown to this build, applied by a newer one
+    pub unknown: bool,
+}
+
+#[derive(Deserialize)]
+struct ColumnDescription {
+    #[serde(rename = "Field")]
+    field: String,
+}
+
+// create the database and the table recording the migrations
+pub async fn prepare(db: &DbConnection) -> Result<()> {
+    db.pool.exec("create_database", &db.queries.render("create_database", &[])?).await?;
+    db.pool.exec("create_schema_migrations_table", &db.queries.render("create_schema_migrations_table", &[])?).await?;
+
+    Ok(())
+}
+
+// the applied migrations by version
+pub async fn applied(db: &DbConnection) -> Result<HashMap<u32, AppliedMigration>> {
+    let sql = db.queries.render("list_schema_migrations", &[])?;
+    let applied: Vec<AppliedMigration> = db.query_as("list_schema_migrations", &sql).await?;
+
+    Ok(applied.into_iter().map(|m| (m.version, m)).collect())
+}
+
+// every migration, known or applied, in version order
+pub async fn status(db: &DbConnection) -> Result<Vec<MigrationStatus>> {
+    prepare(db).await?;
+    let mut applied = applied(db).await?;
+
+    let mut status: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|m| {
+            let record = applied.remove(&m.version);
+
+            MigrationStatus {
+                version: m.version,
+                name: m.name.to_string(),
+                modified: record.as_ref().is_some_and(|r| r.checksum != m.checksum()),
+                applied_at: record.map(|r| r.applied_at),
+                unknown: false,
+            }
+        })
+        .collect();
+
+    status.extend(applied.into_values().map(|r| MigrationStatus {
+        version: r.version,
+        name: r.name,
+        applied_at: Some(r.applied_at),
+        modified: false,
+        unknown: true,
+    }));
+    status.sort_by_key(|m| m.version);
+
+    Ok(status)
+}
+
+// apply the pending migrations up to and including `target`, all of them without one,
+// returns the versions applied
+pub async fn up(db: &DbConnection, target: Option<u32>) -> Result<Vec<u32>> {
+    if let Some(target) = target {
+        if embedded::find(target).is_none() {
+            bail!("unknown migration version {}", target);
+        }
+    }
+
+    prepare(db).await?;
+    let applied = applied(db).await?;
+
+    if let Some(version) = applied.keys().find(|v| embedded::find(**v).is_none()) {
+        bail!("the database has migration {} which this build doesn't know, run a newer build", version);
+    }
+
+    for m in MIGRATIONS {
+        if let Some(record) = applied.get(&m.version) {
+            if record.checksum != m.checksum() {
+                warn!(version = m.version, name = m.name, "migration changed after it was applied, add a new migration instead");
+            }
+        }
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|m| !applied.contains_key(&m.version))
+        .filter(|m| target.map_or(true, |target| m.version <= target))
+        .collect();
+
+    if pending.is_empty() {
+        info!("database is up to date");
+    }
+
+    let mut done = Vec::new();
+
+    for m in pending {
+        info!(version = m.version, name = m.name, "applying migration");
+        run(db, m, &m.up_statements()).await?;
+
+        let sql = db.queries.render("insert_schema_migration", &[
+            ("version", m.version.to_string()),
+            ("name", m.name.to_string()),
+            ("checksum", m.checksum()),
+        ])?;
+        db.pool.exec_once("insert_schema_migration", &sql).await?;
+
+        done.push(m.version);
+    }
+
+    Ok(done)
+}
+
+// revert the applied migrations above `target`, newest first, returns the versions reverted
+pub async fn down(db: &DbConnection, target: u32) -> Result<Vec<u32>> {
+    prepare(db).await?;
+
+    let mut versions: Vec<u32> = applied(db).await?.into_keys().filter(|v| *v > target).collect();
+    versions.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut done = Vec::new();
+
+    for version in versions {
+        let Some(m) = embedded::find(version) else {
+            bail!("can't revert migration {}, this build doesn't know it", version);
+        };
+
+        info!(version = m.version, name = m.name, "reverting migration");
+        run(db, m, &m.down_statements()).await?;
+
+        let sql = db.queries.render("delete_schema_migration", &[("version", m.version.to_string())])?;
+        db.pool.exec_once("delete_schema_migration", &sql).await?;
+
+        done.push(m.version);
+    }
+
+    Ok(done)
+}
+
+// the version `steps` applied migrations below the newest one
+pub async fn version_before(db: &DbConnection, steps: usize) -> Result<u32> {
+    prepare(db).await?;
+
+    let mut versions: Vec<u32> = applied(db).await?.into_keys().collect();
+    versions.sort_unstable_by(|a, b| b.cmp(a));
+
+    Ok(versions.get(steps).copied().unwrap_or(0))
+}
+
+// check the events table has the columns of the shared schema in the same order, the copy
+// from the stage is positional so a mismatch would load the etl output into the wrong columns
+pub async fn check_events_table(db: &DbConnection) -> Result<()> {
+    let sql = db.queries.render("describe_events_table", &[])?;
+    let columns: Vec<ColumnDescription> = db.query_as("describe_events_table", &sql).await?;
+
+    let actual: Vec<&str> = columns.iter().map(|c| c.field.as_str()).collect();
+    let expected: Vec<&str> = EVENT_COLUMNS.iter().map(|c| c.name).collect();
+
+    if actual != expected {
+        bail!(
+            "webshop.events has the columns [{}] but the etl writes [{}], a migration is missing",
+            actual.join(", "),
+            expected.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+async fn run(db: &DbConnection, m: &Migration, statements: &[String]) -> Result<()> {
+    for (i, statement) in statements.iter().enumerate() {
+        db.pool
+            .exec_once(m.name, statement)
+            .await
+            .with_context(|| format!("migration {} failed at statement {} of {}, the statements before it were applied", m.name, i + 1, statements.len()))?;
+    }
+
+    Ok(())
+}
@@ -1,266 +1,516 @@
-use anyhow::{Result, Ok};
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, bail, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::thread;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
 
-use databend_driver::{new_connection, Connection, Row};
+use databend_driver::{RowWithProgress, Value};
 use tokio_stream::StreamExt;
+use tracing::{info, Span};
 
+use crate::cache::MetricsCache;
+use crate::decode::{decode_row, DecodeError};
+use crate::definitions::{MetricDefinition, MetricResult, ValueType};
+use crate::exporter::metrics;
+use crate::migrations;
+use crate::pool::{DbPool, PoolConfig};
+use crate::queries::QueryRegistry;
+use crate::secrets::Secret;
+use webshop_config::{StageWaitSettings, SuspectSettings};
 
-// object for returning messages
-#[derive(Serialize, Deserialize)]
-pub struct Message {
-    pub message: String,
-}
-
-impl Message {
-    pub async fn message(message: String) -> Result<Message> {
-        Ok(Message { message })
-    }
-}
+// the models shared with the etl and the client
+pub use webshop_core::{AttributionMetrics, ChannelAttribution, Event, Message, Metrics, MetricsSnapshot, OrderAttribution, RevenueBySessions, RevenueMetrics, VisitsVariant, LEGACY_VISITS_VARIANT};
 
 #[derive(Clone)]
 pub struct DbConnection {
-    pub conn: Box<dyn Connection>
+    pub pool: Arc<DbPool>,
+    pub cache: Arc<MetricsCache>,
+    pub queries: Arc<QueryRegistry>,
+    // where the revenue and attribution read the amount of an order
+    pub order_amount: OrderAmount,
+    // what counts as a conversion in the precomputed metrics
+    pub conversion: ConversionTypes,
+    // thresholds a re-sessionization flags suspect customers with, none to clear the flags
+    pub suspects: Option<SuspectSettings>,
 }
 
 impl DbConnection {
-    pub async fn init(db_user: &str, db_pwd: &str, db_host: &str, db_port: &u32, db: &str) -> Result<Self> {
-        let dsn = format!("databend://{db_user}:{db_pwd}@{db_host}:{db_port}/{db}?sslmode=disable");
+    pub async fn init(db_user: &str, db_pwd: &Secret, db_host: &str, db_port: &u32, db: &str, pool_config: PoolConfig, cache_ttl: Duration, queries: QueryRegistry) -> Result<Self> {
+        let dsn = format!("databend://{db_user}:{}@{db_host}:{db_port}/{db}?sslmode=disable", db_pwd.expose());
 
-        println!("establishing connection to {}", dsn);
-        let conn = new_connection(&dsn).expect("error making connection");
+        info!(host = db_host, port = db_port, db, "establishing connection to databend");
+        let pool = DbPool::new(dsn, pool_config).expect("error making connection pool");
 
-        Ok(DbConnection {conn})
+        Ok(DbConnection {pool: Arc::new(pool), cache: Arc::new(MetricsCache::new(cache_ttl)), queries: Arc::new(queries), order_amount: OrderAmount::default(), conversion: ConversionTypes::default(), suspects: None})
     }
 
-    pub async fn prepare_db(&self, endpoint: &str, access_key: &str, secret_key: &str, bucket: &str) -> &Self {
-        let conn = &self.conn;
-    
-        // create the database
-        let sql_db_create = "CREATE DATABASE IF NOT EXISTS webshop;";
-        println!("preparing database");
-        conn.exec(sql_db_create).await.expect("error creating database");
+    // read the amount of an order from another field of its attributes
+    pub fn with_order_amount(mut self, order_amount: OrderAmount) -> Self {
+        self.order_amount = order_amount;
+        self
+    }
+
+    // precompute the metrics for other conversion event types than placed_order
+    pub fn with_conversion(mut self, conversion: ConversionTypes) -> Self {
+        self.conversion = conversion;
+        self
+    }
+
+    // flag suspect customers again when re-sessionizing, like the etl does on a load
+    pub fn with_suspects(mut self, suspects: Option<SuspectSettings>) -> Self {
+        self.suspects = suspects;
+        self
+    }
+
+    pub async fn prepare_db(&self, endpoint: &str, access_key: &Secret, secret_key: &Secret, bucket: &str) -> &Self {
+        let conn = &self.pool;
+        let queries = &self.queries;
     
-        // create the table
-        let sql_table_create = "
-            CREATE TABLE IF NOT EXISTS webshop.events (
-                customer_id int,
-                timestamp timestamp,
-                time_diff int,
-                new_session int,
-                session_number int,
-                type varchar
-            );
-        ";
-
-        println!("preparing table");
-        conn.exec(sql_table_create).await.expect("error creating table");
+        // create the database and apply the pending migrations
+        info!("migrating database");
+        migrations::up(self, None).await.expect("error migrating database");
+        migrations::check_events_table(self).await.expect("error checking events table");
+
+        // create the named connection with the bucket credentials, so they are not repeated in the stage
+        let create_connection = queries.render("create_connection", &[
+            ("endpoint", endpoint.to_string()),
+            ("access_key", access_key.expose().to_string()),
+            ("secret_key", secret_key.expose().to_string()),
+        ]).unwrap();
+
+        info!("preparing connection");
+        conn.exec("create_connection", &create_connection).await.expect("error creating connection");
 
         // create the stage for the staged data
-        let create_stage = format!("
-        CREATE STAGE IF NOT EXISTS sessionized
-            URL='s3://{bucket}/'
-            CONNECTION = (
-                ENDPOINT_URL = '{endpoint}'
-                ACCESS_KEY_ID = '{access_key}'
-                SECRET_ACCESS_KEY = '{secret_key}'
-            );
-        ");
+        let create_stage = queries.render("create_stage", &[
+            ("bucket", bucket.to_string()),
+        ]).unwrap();
     
-        println!("preparing stage");
-        conn.exec(&create_stage).await.expect("error creating stage");
+        info!("preparing stage");
+        conn.exec("create_stage", &create_stage).await.expect("error creating stage");
+
+        // check every read query against the prepared database
+        info!("validating queries");
+        queries.validate(conn).await.expect("error validating queries");
 
         self
     
     }
 
-    pub async fn copy_stage_to_table(&self) {
-        let conn = &self.conn;
+    // wait for the etl to stage data, polling with exponential backoff until the timeout
+    pub async fn wait_for_stage(&self, wait: &StageWait) -> Result<&Self> {
+        let select_stage_sql = self.queries.render("select_stage", &[])?;
+        let started = Instant::now();
+        let mut backoff = wait.initial_backoff;
+
+        info!("testing if data is sessionized yet");
+
+        loop {
+            let error = match self.pool.exec("select_stage", &select_stage_sql).await {
+                Ok(_) => return Ok(self),
+                Err(e) => e,
+            };
+
+            let elapsed = started.elapsed();
 
-        // first lets see if data is in the stage
-        let select_stage_sql = "select * from @sessionized limit 10;";
-        println!("testing if data is sessionized yet");
+            if elapsed >= wait.timeout {
+                bail!("no staged data after waiting {:?}, did the etl fail? last error: {}", wait.timeout, error);
+            }
 
-        while let Err(e) = conn.exec(select_stage_sql).await {
-            println!("Error: {}", e);
-            println!("sleeping for 5 seconds");
+            info!(%error, ?backoff, "staged data not available yet, retrying");
 
-            thread::sleep(Duration::from_secs(5));
+            sleep(backoff.min(wait.timeout - elapsed)).await;
+            backoff = (backoff * 2).min(wait.max_backoff);
         }
-        
+    }
+
+    // copy the staged data into the events table, returns the rows copied. files copied before
+    // are skipped, so a restart without a new etl run copies nothing
+    pub async fn copy_stage_to_table(&self) -> i64 {
+        let conn = &self.pool;
+
         // copy data from the stage to the table
-        let copy_stage_to_table = "
-        COPY INTO webshop.events
-        FROM (
-            SELECT *
-            FROM @sessionized
-        );
-        ";
+        let copy_stage_to_table = self.queries.render("copy_stage_to_table", &[]).unwrap();
     
-        println!("copying data from stage into table");
-        conn.exec(copy_stage_to_table).await.expect("error copy data from stage into table");
+        info!("copying data from stage into table");
+        let rows = conn.exec_once("copy_stage_to_table", &copy_stage_to_table).await.expect("error copy data from stage into table");
+        info!(rows, "copied data from stage into table");
+
+        metrics().loaded();
+
+        // metrics computed before this load are stale now
+        self.cache.invalidate();
+
+        rows
+    }
+
+    // precompute the metrics of the loaded data and store them in the metrics and attribution tables
+    pub async fn refresh_metrics(&self, session_length: u32) -> &Self {
+        self.try_refresh_metrics(session_length).await.expect("error refreshing metrics");
+
+        self
+    }
+
+    pub async fn try_refresh_metrics(&self, session_length: u32) -> Result<()> {
+        let conn = &self.pool;
+
+        info!(session_length, conversion = %self.conversion.key(), "refreshing metrics");
+
+        for exclude_suspect in [false, true] {
+            let metrics = self.compute_metrics(exclude_suspect, &self.conversion, VisitsVariant::default()).await?;
+
+            let insert_metrics = self.queries.render("insert_metrics_daily", &[
+                ("session_length", session_length.to_string()),
+                ("exclude_suspect", (exclude_suspect as u8).to_string()),
+                ("median_visits_before_order", metrics.median_visits_before_order.to_string()),
+                ("median_session_duration_minutes_before_order", metrics.median_session_duration_minutes_before_order.to_string()),
+                ("conversion_types", self.conversion.key()),
+                ("visits_variant", VisitsVariant::default().as_str().to_string()),
+            ])?;
+
+            conn.exec_once("insert_metrics_daily", &insert_metrics).await?;
+        }
+
+        // rebuild the attribution of the orders from the current sessions
+        let refresh_order_attribution = self.queries.render("refresh_order_attribution", &[
+            ("amount", self.order_amount.0.clone()),
+        ])?;
+
+        info!("refreshing order attribution");
+        conn.exec("refresh_order_attribution", &refresh_order_attribution).await?;
+
+        // the cached metrics were read before this refresh
+        self.cache.invalidate();
+
+        Ok(())
+    }
+
+    // sessionize the loaded events again with another session length and refresh the metrics
+    pub async fn re_sessionize(&self, session_length: u32) -> Result<()> {
+        let suspects = self.suspects.clone().unwrap_or_default();
+        let re_sessionize_sql = self.queries.render("re_sessionize", &[
+            ("session_length", session_length.to_string()),
+            ("flag_suspects", (self.suspects.is_some() as u8).to_string()),
+            ("max_events_per_minute", suspects.max_events_per_minute.to_string()),
+            ("min_median_gap_seconds", suspects.min_median_gap_seconds.to_string()),
+            ("max_session_events", suspects.max_session_events.to_string()),
+        ])?;
+
+        info!(session_length, "re-sessionizing events");
+        self.pool.exec("re_sessionize", &re_sessionize_sql).await?;
+
+        // the sessions changed, so did every metric computed from them
+        self.cache.invalidate();
+
+        self.try_refresh_metrics(session_length).await
     }
 
-    // pub async fn 
-
-    pub async fn publish_metrics(&self) -> Metrics {
-        let conn = &self.conn;
-
-        // median sessions
-        let median_visits_before_order_sql = "
-            -- identify events where an order was placed
-            with placed_order_events as (
-                select
-                    *,
-                    case
-                        when type = 'placed_order' then 1
-                        else 0
-                    end as placed_order
-                from webshop.events
-            ),
-
-            -- accumulate the orders
-            order_numbers as (
-                select
-                    *,
-                    sum(placed_order) over(partition by customer_id order by timestamp) as order_number
-                from placed_order_events
-            ),
-
-            -- get the max session that occurred for every placed order
-            max_session_per_order as (
-                select
-                    *,
-                    max(session_number) over(partition by customer_id, order_number) as max_session_for_order
-                from order_numbers
-            ),
-
-            -- grab the distinct sets
-            distinct_sessions_orders as (
-                select distinct
-                    customer_id, order_number, max_session_for_order
-                from max_session_per_order
-                order by order_number
-            ),
-
-            -- lag the max session of each placed order to see what the previous one was
-            lag_max_session_of_order as (
-                select
-                    *,
-                    coalesce(lag(max_session_for_order) over(partition by customer_id order by order_number), 0) as lagged_max_session_order
-                from distinct_sessions_orders
-            ),
-
-            -- diff the current - the lag to see how many session occurred between each placed order
-            session_diffs as (
-                select 
-                    *,
-                    max_session_for_order - lagged_max_session_order as session_diff
-                from lag_max_session_of_order
-            ),
-
-            -- median the result
-            final as (
-                select
-                    median(session_diff)
-                from session_diffs
-            )
-
-            select
-                *
-            from final;
-
-            
-        ";
-
-        let median_session_duration_minutes_before_order_sql = "
-            -- identify events where an order was placed
-            with placed_order_events as (
-                select
-                    *,
-                    case
-                        when type = 'placed_order' then 1
-                        else 0
-                    end as placed_order
-                from webshop.events
-            ),
-
-            -- accumulate the orders
-            order_numbers as (
-                select
-                    *,
-                    sum(placed_order) over(partition by customer_id order by timestamp) as order_number
-                from placed_order_events
-            ),
-
-            -- get all events before the first order which also bring all sessions before first order
-            events_before_first_order as (
-                select
-                    *
-                from order_numbers
-                where order_number = 0
-            ),
-
-            -- get duration of each session
-            session_metrics as (
-                select
-                    customer_id,
-                    timestamp,
-                    session_number,
-                    min(timestamp) over(partition by customer_id, session_number) as min_session_event,
-                    max(timestamp) over(partition by customer_id, session_number) as max_session_event
-                from events_before_first_order
-            ),
-
-            -- calculate duration
-            session_duration as (
-                select distinct
-                    customer_id,
-                    session_number,
-                    ((max_session_event - min_session_event)/1000000)/60 as session_duration
-                from session_metrics
-            ),
-
-            -- calculate median
-            final as (
-                select
-                    median(session_duration)
-                from session_duration
-            )
-
-            select *
-            from final;
-        ";
-
-        
-        
-        let mv = conn.query_iter(median_visits_before_order_sql).await.unwrap().next().await.unwrap().unwrap();
-        let md = conn.query_iter(median_session_duration_minutes_before_order_sql).await.unwrap().next().await.unwrap().unwrap();
-
-        
-        Metrics {
-            median_visits_before_order: extract_values(mv).await.unwrap(),
-            median_session_duration_minutes_before_order: extract_values(md).await.unwrap()
+    // a page of the sessionized events, optionally of a single customer
+    pub async fn view_events(&self, customer_id: Option<i64>, limit: u32, offset: u32) -> Result<Vec<Event>> {
+        let filters = match customer_id {
+            Some(customer_id) => format!("customer_id = {customer_id}"),
+            None => "1 = 1".to_string(),
+        };
+
+        let select_events_sql = self.queries.render("select_events", &[
+            ("filters", filters),
+            ("limit", limit.to_string()),
+            ("offset", offset.to_string()),
+        ])?;
+
+        self.query_as("select_events", &select_events_sql).await
+    }
+
+    // a page of the attributed orders, optionally of a single customer
+    pub async fn view_attribution(&self, customer_id: Option<i64>, limit: u32, offset: u32) -> Result<Vec<OrderAttribution>> {
+        let filters = match customer_id {
+            Some(customer_id) => format!("customer_id = {customer_id}"),
+            None => "1 = 1".to_string(),
+        };
+
+        let select_order_attribution_sql = self.queries.render("select_order_attribution", &[
+            ("filters", filters),
+            ("limit", limit.to_string()),
+            ("offset", offset.to_string()),
+        ])?;
+
+        self.query_as("select_order_attribution", &select_order_attribution_sql).await
+    }
+
+    // orders and revenue by first-touch and last-touch channel, read from the materialized attribution
+    pub async fn attribution_metrics(&self, exclude_suspect: bool) -> Result<AttributionMetrics> {
+        let filters = if exclude_suspect { "is_suspect = 0" } else { "1 = 1" };
+
+        Ok(AttributionMetrics {
+            first_touch: self.attribution_by_channel("first_touch_channel", filters).await?,
+            last_touch: self.attribution_by_channel("last_touch_channel", filters).await?,
+        })
+    }
+
+    async fn attribution_by_channel(&self, touch: &str, filters: &str) -> Result<Vec<ChannelAttribution>> {
+        let attribution_by_channel_sql = self.queries.render("attribution_by_channel", &[
+            ("touch", touch.to_string()),
+            ("filters", filters.to_string()),
+        ])?;
+
+        self.query_as("attribution_by_channel", &attribution_by_channel_sql).await
+    }
+
+    // read the most recently precomputed metrics, computing them live if there are none yet
+    //
+    // only the configured conversion types and the default variant are precomputed, others are
+    // always computed live
+    pub async fn publish_metrics(&self, exclude_suspect: bool, conversion: &ConversionTypes, variant: VisitsVariant) -> Result<Metrics> {
+        let latest_metrics_sql = self.queries.render("latest_metrics", &[
+            ("exclude_suspect", (exclude_suspect as u8).to_string()),
+            ("conversion_types", conversion.key()),
+            ("visits_variant", variant.as_str().to_string()),
+        ])?;
+
+        match self.query_one::<Metrics>("latest_metrics", &latest_metrics_sql).await? {
+            Some(metrics) => Ok(metrics),
+            None => self.compute_metrics(exclude_suspect, conversion, variant).await,
         }
+    }
 
+    // every precomputed metrics row of the conversion types and variant, by session length and
+    // oldest first within each
+    // the variant is the tag of the rows, a `VisitsVariant` or `LEGACY_VISITS_VARIANT`
+    pub async fn metrics_history(&self, exclude_suspect: bool, conversion: &ConversionTypes, variant: &str) -> Result<Vec<MetricsSnapshot>> {
+        let metrics_history_sql = self.queries.render("metrics_history", &[
+            ("exclude_suspect", (exclude_suspect as u8).to_string()),
+            ("conversion_types", conversion.key()),
+            ("visits_variant", variant.to_string()),
+        ])?;
+
+        self.query_as("metrics_history", &metrics_history_sql).await
+    }
+
+    // run a query and decode every row into `T` by column name
+    pub async fn query_as<T: DeserializeOwned>(&self, name: &str, sql: &str) -> Result<Vec<T>> {
+        self.pool.run(name, |conn| async move {
+            let (schema, mut rows) = conn.query_iter_ext(sql).await.map_err(|e| DecodeError::Query(e.to_string()))?;
+            let columns: Vec<String> = schema.fields().iter().map(|f| f.name.clone()).collect();
+
+            let mut decoded = Vec::new();
+
+            while let Some(row) = rows.next().await {
+                // progress updates of the query are interleaved with the rows
+                if let RowWithProgress::Row(row) = row.map_err(|e| DecodeError::Query(e.to_string()))? {
+                    decoded.push(decode_row(&columns, &row)?);
+                }
+            }
+
+            Span::current().record("rows", decoded.len());
+
+            Ok::<_, anyhow::Error>(decoded)
+        }).await
+    }
+
+    // run a query and decode its first row, if any
+    pub async fn query_one<T: DeserializeOwned>(&self, name: &str, sql: &str) -> Result<Option<T>> {
+        Ok(self.query_as(name, sql).await?.into_iter().next())
+    }
+
+    // run a declared metric with the filters given as query parameters
+    pub async fn query_metric(&self, metric: &MetricDefinition, filters: &HashMap<String, String>) -> Result<MetricResult> {
+        let sql = self.queries.render(&metric.query_name(), &[
+            ("filters", metric.where_clause(filters)?),
+        ])?;
+
+        let rows = self.pool.run(&metric.query_name(), |conn| {
+            let sql = &sql;
+
+            async move {
+                let mut rows = conn.query_iter(sql).await?;
+                let mut result = Vec::new();
+
+                while let Some(row) = rows.next().await {
+                    let row = row?;
+                    let mut object = serde_json::Map::new();
+
+                    for (output, value) in metric.outputs.iter().zip(row.values()) {
+                        object.insert(output.name.clone(), to_json(value, output.value_type)?);
+                    }
+
+                    result.push(object);
+                }
+
+                Span::current().record("rows", result.len());
+
+                Ok::<_, anyhow::Error>(result)
+            }
+        }).await?;
+
+        Ok(MetricResult {
+            name: metric.name.clone(),
+            rows,
+        })
+    }
+
+    // compute the metrics live from the events table
+    pub async fn compute_metrics(&self, exclude_suspect: bool, conversion: &ConversionTypes, variant: VisitsVariant) -> Result<Metrics> {
+        let params = [
+            ("events", events(exclude_suspect).to_string()),
+            ("conversion_types", conversion.sql_list()),
+        ];
+        let visits_params = [params.as_slice(), &[("cycles", variant.cycles().to_string())]].concat();
+
+        let median_visits_before_order_sql = self.queries.render("median_visits_before_order", &visits_params)?;
+        let median_session_duration_minutes_before_order_sql = self.queries.render("median_session_duration_minutes_before_order", &params)?;
+
+        let mv: MedianVisits = self.query_one("median_visits_before_order", &median_visits_before_order_sql).await?
+            .ok_or_else(|| anyhow!("median_visits_before_order returned no rows"))?;
+        let md: MedianSessionDuration = self.query_one("median_session_duration_minutes_before_order", &median_session_duration_minutes_before_order_sql).await?
+            .ok_or_else(|| anyhow!("median_session_duration_minutes_before_order returned no rows"))?;
+
+        Ok(Metrics {
+            median_visits_before_order: mv.median_visits_before_order,
+            median_session_duration_minutes_before_order: md.median_session_duration_minutes_before_order,
+        })
+    }
+
+    // compute the revenue metrics live from the events table
+    pub async fn compute_revenue(&self, exclude_suspect: bool) -> Result<RevenueMetrics> {
+        let params = [
+            ("events", events(exclude_suspect).to_string()),
+            ("amount", self.order_amount.0.clone()),
+        ];
+
+        let revenue_totals_sql = self.queries.render("revenue_totals", &params)?;
+        let revenue_by_sessions_sql = self.queries.render("revenue_by_sessions_before_order", &params)?;
+
+        let mut revenue: RevenueMetrics = self.query_one("revenue_totals", &revenue_totals_sql).await?
+            .ok_or_else(|| anyhow!("revenue_totals returned no rows"))?;
+        revenue.by_sessions_before_order = self.query_as("revenue_by_sessions_before_order", &revenue_by_sessions_sql).await?;
+
+        Ok(revenue)
     }
 
 }
 
-// object for viewing metrics
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Metrics {
-    pub median_visits_before_order: f64,
-    pub median_session_duration_minutes_before_order: f64,
+// the events the metrics are computed from, optionally leaving out events flagged as
+// bot/crawler traffic by the etl
+fn events(exclude_suspect: bool) -> &'static str {
+    if exclude_suspect {
+        "(select * from webshop.events where is_suspect = 0)"
+    } else {
+        "webshop.events"
+    }
 }
 
-async fn extract_values(row: Row) -> Result<f64> {
-    let value = row.values()[0].clone();
-    let conversion: Result<f64, _> = value.try_into();
-    let res = conversion.expect("error converting value to f64");
+// field of the attributes of an order event holding its amount, `server.order_amount_attribute`
+#[derive(Debug, Clone)]
+pub struct OrderAmount(pub String);
+
+impl Default for OrderAmount {
+    fn default() -> Self {
+        Self("amount".to_string())
+    }
+}
+
+// the event types counted as a conversion by the session metrics, `placed_order` by default
+//
+// kept sorted and without duplicates, so the same set always has the same key in the cache
+// and in webshop.metrics_daily
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionTypes(Vec<String>);
+
+impl ConversionTypes {
+    pub fn new<S: AsRef<str>>(types: &[S]) -> Result<Self> {
+        let mut types: Vec<String> = types.iter().map(|t| t.as_ref().trim().to_string()).collect();
+        types.sort();
+        types.dedup();
+
+        if types.is_empty() {
+            bail!("at least one conversion event type is needed");
+        }
+        // the types are put into the queries as string literals
+        if let Some(invalid) = types.iter().find(|t| t.is_empty() || !t.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')) {
+            bail!("invalid conversion event type `{}`, only letters, digits, `_` and `-` are allowed", invalid);
+        }
+
+        Ok(Self(types))
+    }
+
+    // a comma separated list, as given in the `conversion` query parameter
+    pub fn parse(list: &str) -> Result<Self> {
+        Self::new(&list.split(',').collect::<Vec<_>>())
+    }
+
+    // the types comma separated, as stored in webshop.metrics_daily
+    pub fn key(&self) -> String {
+        self.0.join(",")
+    }
+
+    // the types as a list of SQL string literals, for `type in (...)`
+    pub fn sql_list(&self) -> String {
+        self.0.iter().map(|t| format!("'{t}'")).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl Default for ConversionTypes {
+    fn default() -> Self {
+        Self(vec!["placed_order".to_string()])
+    }
+}
+
+// how long to wait for the etl to stage data
+#[derive(Debug, Clone)]
+pub struct StageWait {
+    pub timeout: Duration,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for StageWait {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(600),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl From<&StageWaitSettings> for StageWait {
+    fn from(settings: &StageWaitSettings) -> Self {
+        Self {
+            timeout: Duration::from_secs(settings.timeout_seconds),
+            initial_backoff: Duration::from_secs(settings.backoff_seconds),
+            max_backoff: Duration::from_secs(settings.max_backoff_seconds),
+        }
+    }
+}
+
+// convert a database value into JSON of the declared output type
+fn to_json(value: &Value, value_type: ValueType) -> Result<serde_json::Value> {
+    if let Value::Null = value {
+        return Ok(serde_json::Value::Null);
+    }
+
+    let text = value.to_string();
+
+    let json = match value_type {
+        ValueType::String => serde_json::Value::from(text),
+        ValueType::Int => serde_json::Value::from(text.parse::<i64>().map_err(|_| anyhow!("error converting {} to int", text))?),
+        ValueType::Float => serde_json::Value::from(text.parse::<f64>().map_err(|_| anyhow!("error converting {} to float", text))?),
+        ValueType::Bool => serde_json::Value::from(matches!(text.as_str(), "true" | "1")),
+    };
+
+    Ok(json)
+}
+
+// single column results of the metric queries
+#[derive(Deserialize)]
+struct MedianVisits {
+    median_visits_before_order: f64,
+}
 
-    Ok(res)
+#[derive(Deserialize)]
+struct MedianSessionDuration {
+    median_session_duration_minutes_before_order: f64,
 }
 
@@ -0,0 +1,66 @@
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::definitions::{FilterDefinition, MetricDefinition, MetricResult, OutputDefinition, ValueType};
+use crate::handlers;
+use crate::models::{AttributionMetrics, ChannelAttribution, Event, Message, Metrics, MetricsSnapshot, OrderAttribution, RevenueBySessions, RevenueMetrics};
+use crate::pool::PoolStats;
+
+// the OpenAPI 3 contract of the api, served at /openapi.json and browsable at /swagger-ui/
+//
+// every route mounted in `router::rocket` has to be listed in `paths` to show up in the spec
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "webshop metrics api", description = "Session, order and revenue metrics of the webshop events"),
+    paths(
+        handlers::index,
+        handlers::ping,
+        handlers::order_metrics,
+        handlers::order_metrics_history,
+        handlers::revenue_metrics,
+        handlers::attribution_metrics,
+        handlers::pool_metrics,
+        handlers::prometheus_metrics,
+        handlers::list_metrics,
+        handlers::declared_metrics,
+        handlers::view_events,
+        handlers::view_attribution,
+        handlers::re_sessionize,
+    ),
+    components(schemas(
+        Message,
+        Metrics,
+        MetricsSnapshot,
+        RevenueMetrics,
+        RevenueBySessions,
+        AttributionMetrics,
+        ChannelAttribution,
+        OrderAttribution,
+        PoolStats,
+        MetricDefinition,
+        FilterDefinition,
+        OutputDefinition,
+        ValueType,
+        MetricResult,
+        Event,
+    )),
+    modifiers(&ApiKeyAuth),
+    tags(
+        (name = "health", description = "liveness of the api"),
+        (name = "metrics", description = "order and declared metrics"),
+        (name = "operations", description = "usage of the api itself"),
+        (name = "data", description = "the sessionized events and attributed orders"),
+    )
+)]
+pub struct ApiDoc;
+
+// the api keys created with the `keys` cli, sent as a bearer token
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme("api_key", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use tracing::{debug, info};
+
+use crate::pool::DbPool;
+use webshop_core::ddl::ddl;
+
+// embed a query file from the sql directory under its file name
+macro_rules! embed {
+    ($name:literal) => {
+        ($name, include_str!(concat!("../sql/", $name, ".sql")))
+    };
+}
+
+// queries compiled into the binary, a directory passed to `load_dir` can override or extend them
+// (the DDL outside of the migrations is added from webshop-core)
+const EMBEDDED: &[(&str, &str)] = &[
+    embed!("select_stage"),
+    embed!("copy_stage_to_table"),
+    embed!("insert_metrics_daily"),
+    embed!("latest_metrics"),
+    embed!("metrics_history"),
+    embed!("median_visits_before_order"),
+    embed!("median_session_duration_minutes_before_order"),
+    embed!("revenue_totals"),
+    embed!("revenue_by_sessions_before_order"),
+    embed!("refresh_order_attribution"),
+    embed!("attribution_by_channel"),
+    embed!("select_order_attribution"),
+    embed!("insert_api_key"),
+    embed!("revoke_api_key"),
+    embed!("list_api_keys"),
+    embed!("find_api_key"),
+    embed!("select_events"),
+    embed!("re_sessionize"),
+    embed!("list_schema_migrations"),
+    embed!("insert_schema_migration"),
+    embed!("delete_schema_migration"),
+    embed!("describe_events_table"),
+];
+
+// a named SQL query with `{param}` placeholders
+//
+// header comments of the file configure the query:
+//   -- @param <name> <default>   default value of a parameter, also used for validation
+//   -- @skip-validation          don't EXPLAIN the query at startup
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub name: String,
+    pub sql: String,
+    pub defaults: HashMap<String, String>,
+    pub validate: bool,
+}
+
+impl Query {
+    pub fn parse(name: &str, sql: &str) -> Self {
+        let mut defaults = HashMap::new();
+        let mut validate = true;
+
+        for line in sql.lines() {
+            let Some(annotation) = line.trim().strip_prefix("--").map(str::trim) else {
+                continue;
+            };
+
+            if let Some(param) = annotation.strip_prefix("@param") {
+                let mut parts = param.trim().splitn(2, char::is_whitespace);
+
+                if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                    defaults.insert(key.to_string(), value.trim().to_string());
+                }
+            } else if annotation == "@skip-validation" {
+                validate = false;
+            }
+        }
+
+        Self {
+            name: name.to_string(),
+            sql: sql.to_string(),
+            defaults,
+            validate,
+        }
+    }
+
+    // names of all parameters used in the query, in order of appearance
+    pub fn params(&self) -> Vec<String> {
+        let mut params = Vec::new();
+
+        for (start, _) in self.sql.match_indices('{') {
+            let rest = &self.sql[start + 1..];
+
+            if let Some(end) = rest.find('}') {
+                let name = &rest[..end];
+
+                if is_param_name(name) && !params.iter().any(|p| p == name) {
+                    params.push(name.to_string());
+                }
+            }
+        }
+
+        params
+    }
+
+    // whether the query takes a parameter, used in its sql or declared with a default
+    pub fn accepts(&self, name: &str) -> bool {
+        self.defaults.contains_key(name) || self.params().iter().any(|p| p == name)
+    }
+
+    // substitute the parameters, falling back to the defaults of the file
+    //
+    // a single scan from left to right, so a value is never searched for placeholders itself.
+    // braces around anything but a parameter name are kept, they may be part of a string literal
+    pub fn render(&self, params: &[(&str, String)]) -> Result<String> {
+        if let Some((name, _)) = params.iter().find(|(name, _)| !self.accepts(name)) {
+            bail!("unknown parameter `{}` for query `{}`", name, self.name);
+        }
+
+        let mut sql = String::with_capacity(self.sql.len());
+        let mut rest = self.sql.as_str();
+
+        while let Some(start) = rest.find('{') {
+            sql.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let name = rest[1..].find('}').map(|end| &rest[1..end + 1]).filter(|name| is_param_name(name));
+
+            match name {
+                Some(name) => {
+                    let value = params
+                        .iter()
+                        .find(|(key, _)| *key == name)
+                        .map(|(_, value)| value.as_str())
+                        .or_else(|| self.defaults.get(name).map(String::as_str))
+                        .ok_or_else(|| anyhow!("missing parameter `{}` for query `{}`", name, self.name))?;
+
+                    sql.push_str(value);
+                    rest = &rest[name.len() + 2..];
+                }
+                None => {
+                    sql.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        sql.push_str(rest);
+
+        Ok(sql)
+    }
+
+    // only read queries can be checked with EXPLAIN
+    fn is_select(&self) -> bool {
+        let statement = self
+            .sql
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("--"))
+            .unwrap_or_default()
+            .to_lowercase();
+
+        statement.starts_with("select") || statement.starts_with("with")
+    }
+}
+
+fn is_param_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// every query the application runs, looked up by name
+#[derive(Debug, Clone)]
+pub struct QueryRegistry {
+    queries: HashMap<String, Query>,
+}
+
+impl QueryRegistry {
+    // the queries compiled into the binary
+    pub fn embedded() -> Self {
+        let queries = ddl()
+            .into_iter()
+            .chain(EMBEDDED.iter().map(|(name, sql)| (*name, sql.to_string())))
+            .map(|(name, sql)| (name.to_string(), Query::parse(name, &sql)))
+            .collect();
+
+        Self { queries }
+    }
+
+    // add or override queries with the `.sql` files of a directory
+    pub fn load_dir(mut self, dir: &Path) -> Result<Self> {
+        let entries = fs::read_dir(dir).with_context(|| format!("error reading query directory {}", dir.display()))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.extension().and_then(|e| e.to_str()) != Some("sql") {
+                continue;
+            }
+
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow!("invalid query file name {}", path.display()))?
+                .to_string();
+            let sql = fs::read_to_string(&path).with_context(|| format!("error reading query file {}", path.display()))?;
+
+            info!(query = %name, path = %path.display(), "loading query");
+            self.queries.insert(name.clone(), Query::parse(&name, &sql));
+        }
+
+        Ok(self)
+    }
+
+    // add a query, replacing one with the same name
+    pub fn insert(&mut self, query: Query) {
+        self.queries.insert(query.name.clone(), query);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Query> {
+        self.queries.get(name).ok_or_else(|| anyhow!("unknown query `{}`", name))
+    }
+
+    // render a query by name with the given parameters
+    pub fn render(&self, name: &str, params: &[(&str, String)]) -> Result<String> {
+        self.get(name)?.render(params)
+    }
+
+    // EXPLAIN every read query with its default parameters so broken SQL fails at startup
+    pub async fn validate(&self, pool: &DbPool) -> Result<()> {
+        let mut names: Vec<&String> = self.queries.keys().collect();
+        names.sort();
+
+        let mut failures = Vec::new();
+
+        for name in names {
+            let query = &self.queries[name];
+
+            if !query.validate || !query.is_select() {
+                continue;
+            }
+
+            debug!(query = %name, "validating query");
+
+            let result = match query.render(&[]) {
+                Ok(sql) => pool.exec(name, &format!("EXPLAIN {}", sql.trim().trim_end_matches(';'))).await,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                failures.push(format!("{}: {}", name, e));
+            }
+        }
+
+        if !failures.is_empty() {
+            bail!("invalid queries:\n{}", failures.join("\n"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQL: &str = "-- @param events webshop.events
+-- @param limit 10
+-- @skip-validation
+select '{\"json\": {}}', {limit} from {events} where {filters} and {limit} > 0;
+";
+
+    fn query() -> Query {
+        Query::parse("events", SQL)
+    }
+
+    #[test]
+    fn parse_annotations() {
+        let query = query();
+
+        assert_eq!(query.defaults["events"], "webshop.events");
+        assert_eq!(query.defaults["limit"], "10");
+        assert!(!query.validate);
+        assert!(Query::parse("other", "select 1").validate);
+    }
+
+    #[test]
+    fn params_in_order_of_appearance() {
+        assert_eq!(query().params(), ["limit", "events", "filters"]);
+    }
+
+    #[test]
+    fn render_with_defaults() {
+        let sql = query().render(&[("filters", "1 = 1".to_string())]).unwrap();
+
+        assert!(sql.ends_with("select '{\"json\": {}}', 10 from webshop.events where 1 = 1 and 10 > 0;\n"), "{}", sql);
+    }
+
+    #[test]
+    fn render_values_are_not_substituted_again() {
+        let sql = query()
+            .render(&[("filters", "name = '{limit}'".to_string()), ("limit", "5".to_string())])
+            .unwrap();
+
+        assert!(sql.contains("where name = '{limit}' and 5 > 0"), "{}", sql);
+    }
+
+    #[test]
+    fn render_fails_on_a_missing_parameter() {
+        let error = query().render(&[]).unwrap_err().to_string();
+
+        assert_eq!(error, "missing parameter `filters` for query `events`");
+    }
+
+    #[test]
+    fn render_fails_on_an_unknown_parameter() {
+        let error = query()
+            .render(&[("filters", "1 = 1".to_string()), ("offset", "0".to_string())])
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(error, "unknown parameter `offset` for query `events`");
+    }
+}
@@ -0,0 +1,349 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method, Status};
+use rocket::request::Request;
+use rocket::serde::json::Json;
+use rocket::{Data, Response};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::auth::hash_key;
+use crate::exporter::metrics;
+use crate::models::Message;
+use webshop_config::{BudgetSettings, RateLimitSettings};
+
+// the route a request over its budget is rewritten to
+const RATE_LIMITED_URI: &str = "/__rate_limited";
+
+// above this many buckets the ones that refilled completely are dropped
+const MAX_BUCKETS: usize = 10_000;
+
+// size and refill rate of a token bucket, one token per request
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    // requests that may be made at once
+    pub burst: u32,
+    // requests per minute in the long run
+    pub per_minute: u32,
+}
+
+impl Budget {
+    fn refill_per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+// budgets of the rate limiter
+//
+// standard requests are limited per api key and per ip, heavy ones (anything under /data:
+// re-sessionization and the raw events) draw from separate, smaller buckets
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub per_key: Budget,
+    pub per_ip: Budget,
+    pub heavy_per_key: Budget,
+    pub heavy_per_ip: Budget,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_key: Budget { burst: 20, per_minute: 60 },
+            per_ip: Budget { burst: 40, per_minute: 120 },
+            heavy_per_key: Budget { burst: 2, per_minute: 2 },
+            heavy_per_ip: Budget { burst: 4, per_minute: 4 },
+        }
+    }
+}
+
+impl From<BudgetSettings> for Budget {
+    fn from(settings: BudgetSettings) -> Self {
+        Self {
+            burst: settings.burst,
+            per_minute: settings.per_minute,
+        }
+    }
+}
+
+impl From<&RateLimitSettings> for RateLimitConfig {
+    fn from(settings: &RateLimitSettings) -> Self {
+        Self {
+            per_key: settings.per_key.into(),
+            per_ip: settings.per_ip.into(),
+            heavy_per_key: settings.heavy_per_key.into(),
+            heavy_per_ip: settings.heavy_per_ip.into(),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, budget: &Budget, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * budget.refill_per_second()).min(budget.burst as f64);
+        self.updated = now;
+    }
+
+    // time until the bucket holds the given number of tokens
+    fn time_until(&self, budget: &Budget, tokens: f64) -> Duration {
+        let missing = (tokens - self.tokens).max(0.0);
+
+        if missing == 0.0 || budget.per_minute == 0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(missing / budget.refill_per_second())
+    }
+}
+
+// outcome of the check of a request, turned into the X-RateLimit-* headers of the response
+#[derive(Debug, Clone, Copy)]
+struct Decision {
+    limit: u32,
+    remaining: u32,
+    reset: Duration,
+    retry_after: Option<Duration>,
+}
+
+// token bucket rate limiting of /metrics and /data, per api key and per client ip
+//
+// a fairing can't answer a request itself, so a request over its budget is rewritten
+// to an internal route that responds with a 429
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // take a token from every bucket of the request, or from none if any of them is empty
+    fn check(&self, limits: &[(String, Budget)], now: Instant) -> Decision {
+        let mut buckets = self.buckets.lock().expect("lock rate limit buckets");
+
+        if buckets.len() > MAX_BUCKETS {
+            let config = &self.config;
+            let largest = [config.per_key, config.per_ip, config.heavy_per_key, config.heavy_per_ip]
+                .iter()
+                .map(|b| b.burst as f64 / b.refill_per_second().max(f64::MIN_POSITIVE))
+                .fold(0.0, f64::max);
+
+            // a bucket untouched for longer than any bucket takes to refill is full again anyway
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated).as_secs_f64() < largest);
+        }
+
+        for (key, budget) in limits {
+            buckets
+                .entry(key.clone())
+                .or_insert(Bucket {
+                    tokens: budget.burst as f64,
+                    updated: now,
+                })
+                .refill(budget, now);
+        }
+
+        let allowed = limits.iter().all(|(key, _)| buckets[key].tokens >= 1.0);
+
+        if allowed {
+            for (key, _) in limits {
+                buckets.get_mut(key).expect("bucket exists").tokens -= 1.0;
+            }
+        }
+
+        // report the bucket closest to running out
+        let (key, budget) = limits
+            .iter()
+            .min_by(|(a, _), (b, _)| buckets[a].tokens.total_cmp(&buckets[b].tokens))
+            .expect("every request has a bucket");
+        let bucket = &buckets[key];
+
+        Decision {
+            limit: budget.burst,
+            remaining: bucket.tokens.max(0.0) as u32,
+            reset: bucket.time_until(budget, budget.burst as f64),
+            retry_after: (!allowed).then(|| {
+                limits
+                    .iter()
+                    .map(|(key, budget)| buckets[key].time_until(budget, 1.0))
+                    .max()
+                    .unwrap_or_default()
+            }),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limiter",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let path = request.uri().path();
+        let heavy = path.starts_with("/data");
+
+        if !heavy && !path.starts_with("/metrics") {
+            return;
+        }
+
+        let (key_budget, ip_budget) = if heavy {
+            (self.config.heavy_per_key, self.config.heavy_per_ip)
+        } else {
+            (self.config.per_key, self.config.per_ip)
+        };
+        let class = if heavy { "heavy" } else { "standard" };
+
+        let mut limits = Vec::new();
+
+        // keys are only kept as hashes, even in memory
+        let headers = request.headers();
+        let key = headers
+            .get_one("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .or_else(|| headers.get_one("X-Api-Key"))
+            .map(str::trim)
+            .filter(|key| !key.is_empty());
+        if let Some(key) = key {
+            limits.push((format!("{class}:key:{}", hash_key(key)), key_budget));
+        }
+
+        let ip = request
+            .client_ip()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        limits.push((format!("{class}:ip:{ip}"), ip_budget));
+
+        let decision = self.check(&limits, Instant::now());
+        request.local_cache(|| Some(decision));
+
+        if let Some(retry_after) = decision.retry_after {
+            warn!(%ip, class, retry_after_s = retry_after.as_secs_f64(), uri = %request.uri(), "rate limited");
+            metrics().error("rate_limited");
+
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(RATE_LIMITED_URI).expect("valid rate limited uri"));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(decision) = request.local_cache(|| None::<Decision>) else {
+            return;
+        };
+
+        response.set_header(Header::new("X-RateLimit-Limit", decision.limit.to_string()));
+        response.set_header(Header::new("X-RateLimit-Remaining", decision.remaining.to_string()));
+        response.set_header(Header::new("X-RateLimit-Reset", decision.reset.as_secs_f64().ceil().to_string()));
+
+        if let Some(retry_after) = decision.retry_after {
+            // Retry-After is in whole seconds, round up so the retry doesn't come too early
+            response.set_header(Header::new("Retry-After", (retry_after.as_secs_f64().ceil() as u64).max(1).to_string()));
+        }
+    }
+}
+
+// where requests over their budget end up
+#[get("/__rate_limited")]
+pub async fn rate_limited() -> (Status, Json<Message>) {
+    let message = "Too many requests, retry after the number of seconds in the Retry-After header".to_string();
+
+    (Status::TooManyRequests, Json(Message { message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Budget = Budget { burst: 2, per_minute: 60 };
+
+    fn limits(keys: &[&str]) -> Vec<(String, Budget)> {
+        keys.iter().map(|key| (key.to_string(), BUDGET)).collect()
+    }
+
+    #[test]
+    fn bucket_empties() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+        let limits = limits(&["key"]);
+
+        assert_eq!(limiter.check(&limits, now).retry_after, None);
+        assert_eq!(limiter.check(&limits, now).retry_after, None);
+
+        let decision = limiter.check(&limits, now);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.limit, 2);
+        // one token a second, a full bucket takes two
+        assert_eq!(decision.retry_after, Some(Duration::from_secs(1)));
+        assert_eq!(decision.reset, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+        let limits = limits(&["key"]);
+
+        limiter.check(&limits, now);
+        limiter.check(&limits, now);
+        assert!(limiter.check(&limits, now).retry_after.is_some());
+
+        // half a token isn't enough
+        assert!(limiter.check(&limits, now + Duration::from_millis(500)).retry_after.is_some());
+        assert_eq!(limiter.check(&limits, now + Duration::from_secs(1)).retry_after, None);
+
+        // never above the burst, however long the bucket was left alone
+        let decision = limiter.check(&limits, now + Duration::from_secs(3600));
+        assert_eq!(decision.retry_after, None);
+        assert_eq!(decision.remaining, 1);
+    }
+
+    #[test]
+    fn rejection_spends_no_tokens() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+        let both = vec![
+            ("key".to_string(), Budget { burst: 1, per_minute: 60 }),
+            ("ip".to_string(), Budget { burst: 5, per_minute: 60 }),
+        ];
+
+        assert_eq!(limiter.check(&both, now).retry_after, None);
+
+        // the key is out of tokens, so the ip keeps its own
+        let decision = limiter.check(&both, now);
+        assert_eq!(decision.retry_after, Some(Duration::from_secs(1)));
+        assert_eq!(decision.remaining, 0);
+
+        let ip = limiter.check(&both[1..], now);
+        assert_eq!(ip.retry_after, None);
+        assert_eq!(ip.remaining, 3);
+    }
+
+    #[test]
+    fn retry_after_waits_for_the_slowest_bucket() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+        let both = vec![
+            ("key".to_string(), Budget { burst: 1, per_minute: 60 }),
+            ("ip".to_string(), Budget { burst: 1, per_minute: 6 }),
+        ];
+
+        limiter.check(&both, now);
+
+        // the ip bucket refills a token every 10 seconds
+        assert_eq!(limiter.check(&both, now).retry_after, Some(Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,104 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{Data, Orbit, Response, Rocket};
+use std::time::Instant;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::exporter::metrics;
+
+// id of a request, taken from the X-Request-Id header or generated
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+// when the request arrived, to log its latency
+struct RequestStart(Instant);
+
+impl RequestId {
+    fn of(request: &Request<'_>) -> &RequestId {
+        request.local_cache(|| {
+            let id = request
+                .headers()
+                .get_one("X-Request-Id")
+                .filter(|id| !id.is_empty() && id.len() <= 128)
+                .map(str::to_string)
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+            RequestId(id)
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(request).clone())
+    }
+}
+
+// logs every request with its id, status and latency and echoes the id in the X-Request-Id header
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logger",
+            kind: Kind::Liftoff | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = rocket.config();
+
+        info!(address = %config.address, port = config.port, "api listening");
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+
+        let request_id = RequestId::of(request);
+
+        info!(
+            request_id = %request_id.0,
+            method = %request.method(),
+            uri = %request.uri(),
+            "request started"
+        );
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let request_id = RequestId::of(request);
+        let latency = request.local_cache(|| RequestStart(Instant::now())).0.elapsed();
+        let status = response.status();
+
+        // label by route template rather than the full uri to keep the number of series bounded
+        let route = request.route().map(|r| r.uri.to_string()).unwrap_or_else(|| "unmatched".to_string());
+        metrics().observe_request(request.method().as_str(), &route, status.code, latency);
+
+        if status.code >= 500 {
+            warn!(
+                request_id = %request_id.0,
+                method = %request.method(),
+                uri = %request.uri(),
+                status = status.code,
+                latency_ms = latency.as_secs_f64() * 1000.0,
+                "request failed"
+            );
+        } else {
+            info!(
+                request_id = %request_id.0,
+                method = %request.method(),
+                uri = %request.uri(),
+                status = status.code,
+                latency_ms = latency.as_secs_f64() * 1000.0,
+                "request finished"
+            );
+        }
+
+        response.set_header(Header::new("X-Request-Id", request_id.0.clone()));
+    }
+}
@@ -1,65 +1,92 @@
 use color_eyre::eyre::{Error, Result};
+use std::time::Duration;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
+use crate::auth::KeyStore;
+use crate::definitions::MetricDefinitions;
 use crate::handlers::*;
-use crate::models::DbConnection;
-// use etl::etl::Data;
+use crate::models::{ConversionTypes, DbConnection, OrderAmount, StageWait};
+use crate::openapi::ApiDoc;
+use crate::pool::PoolConfig;
+use crate::queries::QueryRegistry;
+use crate::rate_limit::{rate_limited, RateLimitConfig, RateLimiter};
+use crate::request_log::RequestLogger;
+use webshop_config::logging::init_logging;
+use webshop_config::Config;
 
 #[rocket::main]
-pub async fn rocket() -> Result<(), Error> {
+pub async fn rocket(config: Config) -> Result<(), Error> {
     // pretty error handling
     color_eyre::install()?;
 
-    // load environment variables
-    // dotenvy::dotenv().expect("error loading .env vars");
-
-    // get URL & SESSION_LENGTH env vars
-    // let url = std::env::var("URL").expect("error getting URL");
-    // let session_length: u32 = std::env::var("SESSION_LENGTH")
-    //     .expect("error getting SESSION_LENGTH")
-    //     .parse::<u32>()
-    //     .expect("error parsing SESSION_LENGTH into u32");
-    // let data_path = std::env::var("DATA_PATH").expect("error getting DATA_PATH");
-
-    // env vars for accessiing minio
-    // let region = std::env::var("BUCKET_REGION").expect("error getting BUCKET_REGION");
-    let endpoint = std::env::var("BUCKET_ENDPOINT").expect("error getting BUCKET_ENDPOINT");
-    let access_key = std::env::var("ACCESS_KEY").expect("error getting ACCESS_KEY");
-    let secret_key = std::env::var("SECRET_KEY").expect("error getting SECRET_KEY");
-    let bucket = std::env::var("STAGING_BUCKET").expect("error getting bucket name");
-
-    // env vars for connecting to databend
-    let db_user = std::env::var("DATABEND_USER").expect("error getting DATABEND_USER");
-    let db_pwd = std::env::var("DATABEND_PWD").expect("error getting DATABEND_PWD");
-    let db_host = std::env::var("DATABEND_HOST").expect("error getting DATABEND_HOST");
-    let db_port = std::env::var("DATABEND_PORT")
-        .expect("error getting DATABEND_PORT")
-        .parse::<u32>()
-        .expect("error parsing DATABEND_PORT into u32");
-    let db = std::env::var("DATABEND_DB").expect("error getting DATABEND_DB");
-
-    // create the initial state
-    // initialize the Data
-    // extract the data: request it from the URL, sessionize, then store it
-
-    // Data::init().await.expect("error initializing data")
-    //     .extract(&url).await.expect("error extracting data")
-    //     .transform(session_length).await.expect("error transforming data")
-    //     .load(&data_path, &region, &endpoint, &access_key, &secret_key).await;
-
-
-    let state = DbConnection::init(&db_user, &db_pwd, &db_host, &db_port, &db).await.expect("error connecting to db");
+    // structured logging, configured by RUST_LOG and LOG_FORMAT
+    init_logging();
+
+    // the settings were read and validated by the binary, see the webshop-config crate
+    let databend = &config.databend;
+    let s3 = &config.s3;
+    let server = &config.server;
+
+    let access_key = s3.access_key.clone().expect("error getting s3.access_key");
+    let secret_key = s3.secret_key.clone().expect("error getting s3.secret_key");
+
+    // size of the connection pool, per query timeout and retries of transient errors
+    let pool_config = PoolConfig::from(&databend.pool);
+
+    // how long to wait for the etl to stage the data before giving up
+    let stage_wait = StageWait::from(&databend.stage_wait);
+
+    // token bucket budgets per api key and per ip, heavy requests (/data) have their own
+    let rate_limits = RateLimitConfig::from(&server.rate_limit);
+
+    // queries compiled into the binary, optionally overridden by the .sql files of server.sql_dir
+    let queries = match &server.sql_dir {
+        Some(dir) => QueryRegistry::embedded().load_dir(dir).expect("error loading server.sql_dir"),
+        None => QueryRegistry::embedded(),
+    };
+
+    // declared metrics compiled into the binary, or the ones of server.metrics_config
+    let definitions = match &server.metrics_config {
+        Some(path) => MetricDefinitions::load(path).expect("error loading server.metrics_config"),
+        None => MetricDefinitions::embedded(),
+    };
+    let queries = definitions.register(queries).expect("error registering metric definitions");
+
+    let state = DbConnection::init(&databend.user, &databend.password, &databend.host, &databend.port, &databend.database, pool_config, Duration::from_secs(server.cache_ttl_seconds), queries).await.expect("error connecting to db")
+        .with_order_amount(OrderAmount(server.order_amount_attribute.clone()))
+        .with_conversion(ConversionTypes::new(&config.session.conversion_types).expect("error reading session.conversion_types"))
+        .with_suspects(config.session.flag_suspects.then(|| config.session.suspects.clone()));
 
     state
-        .prepare_db(&endpoint, &access_key, &secret_key, &bucket).await
-        .copy_stage_to_table().await;
+        .prepare_db(&s3.endpoint, &access_key, &secret_key, &s3.bucket).await
+        .wait_for_stage(&stage_wait).await.expect("error waiting for staged data");
+
+    // only new data is sessionized with session.length, after a restart without a new etl run the
+    // table keeps its sessions, maybe re-sessionized with another length, and their metrics
+    if state.copy_stage_to_table().await > 0 {
+        state.refresh_metrics(config.session.length).await;
+    }
 
     // setup router with several mounts and the handlers that belong to each mount
     // pass the state around to the handlers
-    let _ = rocket::build()
+    // requests are logged by the request logger, rocket's own logger is turned off
+    let figment = rocket::Config::figment()
+        .merge(("address", &server.address))
+        .merge(("port", server.port))
+        .merge(("log_level", rocket::config::LogLevel::Off));
+
+    let _ = rocket::custom(figment)
+        .attach(RequestLogger)
+        .attach(RateLimiter::new(rate_limits))
         .manage(state)
-        .mount("/", routes![index, ping])
-        // .mount("/data", routes![view_data, re_sessionize,])
-        .mount("/metrics", routes![order_metrics,])
+        .manage(definitions)
+        .manage(KeyStore::new(Duration::from_secs(server.key_cache_ttl_seconds)))
+        .mount("/", routes![index, ping, rate_limited])
+        // the OpenAPI spec at /openapi.json and an interactive ui over it
+        .mount("/", SwaggerUi::new("/swagger-ui/<_..>").url("/openapi.json", ApiDoc::openapi()))
+        .mount("/data", routes![view_events, view_attribution, re_sessionize,])
+        .mount("/metrics", routes![order_metrics, order_metrics_history, revenue_metrics, attribution_metrics, pool_metrics, prometheus_metrics, list_metrics, declared_metrics,])
         .launch()
         .await?;
 
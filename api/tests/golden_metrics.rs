@@ -0,0 +1,251 @@
+// golden data tests of the metric queries and the re-sessionization against a local databend
+//
+// they are ignored by default, run them with the databend of `task run` up:
+//   cargo test -p api --test golden_metrics -- --ignored
+// the connection comes from the usual settings (CONFIG_FILE, DATABEND_* and WEBSHOP_* env vars),
+// every test writes the events to a database of its own and drops it afterwards
+
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use api::models::{DbConnection, VisitsVariant};
+use api::pool::PoolConfig;
+use api::queries::QueryRegistry;
+use webshop_config::{Config, ConfigArgs};
+use webshop_core::schema::EVENT_COLUMNS;
+
+// the sessions of every customer, each with the types of its events in order
+//
+//   customer 1: orders in session 3 and 5, then browses 3 more sessions
+//   customer 2: orders twice in its only session
+//   customer 3: orders in session 4
+//   customer 4: never orders
+//
+// which gives the cycles
+//   first:     4 (customer 1), 1 (customer 2), 5 (customer 3)
+//   completed: 2 (customer 1), 1 (customer 2)
+//   open:      3 (customer 1), 6 (customer 4)
+const GOLDEN: &[(i64, &[&[&str]])] = &[
+    (1, &[
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+        &["page_view", "placed_order"],
+        &["page_view"],
+        &["page_view", "placed_order"],
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+    ]),
+    (2, &[
+        &["page_view", "placed_order", "page_view", "placed_order"],
+    ]),
+    (3, &[
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+        &["page_view", "placed_order"],
+    ]),
+    (4, &[
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+        &["page_view"],
+    ]),
+];
+
+#[derive(Deserialize)]
+struct MedianVisits {
+    median_visits_before_order: f64,
+}
+
+async fn connect() -> Result<DbConnection> {
+    let config = Config::load(&ConfigArgs::default())?;
+    let db = &config.databend;
+
+    DbConnection::init(&db.user, &db.password, &db.host, &db.port, &db.database, PoolConfig::from(&db.pool), Duration::ZERO, QueryRegistry::embedded()).await
+}
+
+// (re)create an empty events table in the database with the columns of webshop.events, returns
+// the table
+async fn create_events(db: &DbConnection, database: &str) -> Result<String> {
+    let events = format!("{database}.events");
+    let columns = EVENT_COLUMNS
+        .iter()
+        .map(|c| format!("{} {}", c.name, c.sql_type))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    db.pool.exec("golden", &format!("CREATE DATABASE IF NOT EXISTS {database}")).await?;
+    db.pool.exec("golden", &format!("DROP TABLE IF EXISTS {events}")).await?;
+    db.pool.exec("golden", &format!("CREATE TABLE {events} ({columns})")).await?;
+
+    Ok(events)
+}
+
+// (re)create the golden events table of the database, returns the table
+async fn load_golden(db: &DbConnection, database: &str) -> Result<String> {
+    let events = create_events(db, database).await?;
+    let mut rows = Vec::new();
+
+    for (customer_id, sessions) in GOLDEN {
+        for (session_number, events) in sessions.iter().enumerate() {
+            for (i, event_type) in events.iter().enumerate() {
+                // an hour per session, a minute per event
+                let new_session = (session_number > 0 && i == 0) as u8;
+                rows.push(format!(
+                    "({customer_id}, '2023-01-01 {session_number:02}:{i:02}:00', 0, {new_session}, {session_number}, '{event_type}', 0, NULL)"
+                ));
+            }
+        }
+    }
+
+    db.pool.exec("golden", &format!("INSERT INTO {events} VALUES {}", rows.join(", "))).await?;
+
+    Ok(events)
+}
+
+async fn median_visits(db: &DbConnection, events: &str, conversion_types: &str, variant: VisitsVariant) -> Result<f64> {
+    let sql = db.queries.render("median_visits_before_order", &[
+        ("events", events.to_string()),
+        ("conversion_types", conversion_types.to_string()),
+        ("cycles", variant.cycles().to_string()),
+    ])?;
+
+    let row: Option<MedianVisits> = db.query_one("median_visits_before_order", &sql).await?;
+
+    Ok(row.expect("median_visits_before_order returns a row").median_visits_before_order)
+}
+
+#[rocket::async_test]
+#[ignore = "needs a local databend"]
+async fn median_visits_before_order_variants() {
+    let db = connect().await.expect("error connecting to databend");
+    let events = load_golden(&db, "webshop_golden_variants").await.expect("error loading the golden events");
+
+    // first: 1, 4, 5
+    assert_eq!(median_visits(&db, &events, "'placed_order'", VisitsVariant::FirstOrder).await.unwrap(), 4.0);
+    // first and completed: 1, 1, 2, 4, 5
+    assert_eq!(median_visits(&db, &events, "'placed_order'", VisitsVariant::CompletedCycles).await.unwrap(), 2.0);
+    // every cycle: 1, 1, 2, 3, 4, 5, 6
+    assert_eq!(median_visits(&db, &events, "'placed_order'", VisitsVariant::AllCycles).await.unwrap(), 3.0);
+
+    db.pool.exec("golden", "DROP DATABASE IF EXISTS webshop_golden_variants").await.unwrap();
+}
+
+#[rocket::async_test]
+#[ignore = "needs a local databend"]
+async fn median_visits_before_order_of_other_conversions() {
+    let db = connect().await.expect("error connecting to databend");
+    let events = load_golden(&db, "webshop_golden_conversions").await.expect("error loading the golden events");
+
+    // every session starts with a page view, so with page views as the conversion every session is a
+    // cycle of one and nothing is left open
+    assert_eq!(median_visits(&db, &events, "'page_view'", VisitsVariant::AllCycles).await.unwrap(), 1.0);
+    // several types: the first conversion of every customer is the page view opening its first session
+    assert_eq!(median_visits(&db, &events, "'placed_order', 'page_view'", VisitsVariant::FirstOrder).await.unwrap(), 1.0);
+
+    db.pool.exec("golden", "DROP DATABASE IF EXISTS webshop_golden_conversions").await.unwrap();
+}
+
+// events as the etl loads them with a session length of 30: time_diff in whole minutes and a tie
+// at 00:10:30, the page view first in the input. customer 1 is flagged by an earlier run, customer
+// 2 sends 40 page views half a second apart
+//
+//   customer, timestamp, time_diff, new_session, session_number, type, is_suspect
+const LOADED: &[(i64, &str, i64, i64, i64, &str, i64)] = &[
+    (1, "2023-01-01 00:00:00", 0, 0, 0, "page_view", 1),
+    (1, "2023-01-01 00:10:30", 10, 0, 0, "page_view", 1),
+    (1, "2023-01-01 00:10:30", 0, 0, 0, "add_to_cart", 1),
+    (1, "2023-01-01 00:15:30", 5, 0, 0, "page_view", 1),
+    // 30.5 minutes after the last event
+    (1, "2023-01-01 00:46:00", 30, 1, 1, "placed_order", 1),
+    (1, "2023-01-01 00:46:20", 0, 0, 1, "page_view", 1),
+];
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct SessionizedRow {
+    customer_id: i64,
+    time_diff: i64,
+    new_session: i64,
+    session_number: i64,
+    #[serde(rename = "type")]
+    event_type: String,
+    is_suspect: i64,
+}
+
+async fn load_sessionized(db: &DbConnection, database: &str) -> Result<String> {
+    let events = create_events(db, database).await?;
+
+    let mut rows: Vec<String> = LOADED
+        .iter()
+        .map(|(customer_id, timestamp, time_diff, new_session, session_number, event_type, is_suspect)| {
+            format!("({customer_id}, '{timestamp}', {time_diff}, {new_session}, {session_number}, '{event_type}', {is_suspect}, NULL)")
+        })
+        .collect();
+    for i in 0..40 {
+        let timestamp = format!("2023-01-01 00:00:{:02}.{}", i / 2, if i % 2 == 0 { "0" } else { "5" });
+        rows.push(format!("(2, '{timestamp}', 0, 0, 0, 'page_view', 0, NULL)"));
+    }
+
+    db.pool.exec("golden", &format!("INSERT INTO {events} VALUES {}", rows.join(", "))).await?;
+
+    Ok(events)
+}
+
+async fn re_sessionize(db: &DbConnection, events: &str, session_length: u32, flag_suspects: bool) -> Result<Vec<SessionizedRow>> {
+    let sql = db.queries.render("re_sessionize", &[
+        ("events", events.to_string()),
+        ("session_length", session_length.to_string()),
+        ("flag_suspects", (flag_suspects as u8).to_string()),
+    ])?;
+    db.pool.exec("golden", &sql).await?;
+
+    let select = format!("select customer_id, time_diff, new_session, session_number, type, is_suspect from {events} order by customer_id, timestamp, time_diff desc");
+
+    db.query_as("golden", &select).await
+}
+
+// the rows of customer 1 in timestamp order with the given time_diff, new_session and
+// session_number and suspect flag
+fn customer_1(sessions: &[(i64, i64, i64)], is_suspect: i64) -> Vec<SessionizedRow> {
+    LOADED
+        .iter()
+        .zip(sessions)
+        .map(|((customer_id, _, _, _, _, event_type, _), (time_diff, new_session, session_number))| SessionizedRow {
+            customer_id: *customer_id,
+            time_diff: *time_diff,
+            new_session: *new_session,
+            session_number: *session_number,
+            event_type: event_type.to_string(),
+            is_suspect,
+        })
+        .collect()
+}
+
+#[rocket::async_test]
+#[ignore = "needs a local databend"]
+async fn re_sessionize_like_the_etl() {
+    let db = connect().await.expect("error connecting to databend");
+    let events = load_sessionized(&db, "webshop_golden_re_sessionize").await.expect("error loading the sessionized events");
+
+    // gaps of 10.5, 0, 5, 30.5 and a third of a minute, the tie splits off with the page view
+    let rows = re_sessionize(&db, &events, 10, true).await.unwrap();
+    assert_eq!(rows[..6], customer_1(&[(0, 0, 0), (10, 1, 1), (0, 0, 1), (5, 0, 1), (30, 1, 2), (0, 0, 2)], 0));
+
+    // customer 2 is flagged on 40 events in 19.5 seconds, a single session
+    assert_eq!(rows.len(), 46);
+    assert!(rows[6..].iter().all(|row| row.customer_id == 2 && row.session_number == 0 && row.is_suspect == 1));
+
+    // the length of the load gives back its sessions
+    let rows = re_sessionize(&db, &events, 30, false).await.unwrap();
+    assert_eq!(rows[..6], customer_1(&[(0, 0, 0), (10, 0, 0), (0, 0, 0), (5, 0, 0), (30, 1, 1), (0, 0, 1)], 0));
+    assert!(rows[6..].iter().all(|row| row.is_suspect == 0));
+
+    db.pool.exec("golden", "DROP DATABASE IF EXISTS webshop_golden_re_sessionize").await.unwrap();
+}
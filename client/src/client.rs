@@ -0,0 +1,372 @@
+use reqwest::{Method, RequestBuilder, Url};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::error::ClientError;
+use webshop_core::definitions::{MetricDefinition, MetricResult};
+use webshop_core::{AttributionMetrics, Event, Message, Metrics, MetricsSnapshot, OrderAttribution, PoolStats, RevenueMetrics, VisitsVariant, LEGACY_VISITS_VARIANT};
+
+// settings of the client
+#[derive(Clone)]
+pub struct ClientConfig {
+    // time a single attempt of a call may take
+    pub timeout: Duration,
+    // retries of a call after a transient error
+    pub max_retries: u32,
+    // wait before the first retry, doubled for every following one
+    pub initial_backoff: Duration,
+    // key sent as a bearer token, created with the `keys` cli of the api
+    pub api_key: Option<String>,
+}
+
+// the api key never shows up in Debug output
+impl fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("timeout", &self.timeout)
+            .field("max_retries", &self.max_retries)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("api_key", &self.api_key.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            api_key: None,
+        }
+    }
+}
+
+// async client of the metrics api
+#[derive(Debug, Clone)]
+pub struct Client {
+    base: Url,
+    http: reqwest::Client,
+    config: ClientConfig,
+}
+
+impl Client {
+    // a client of the api at the given base url, e.g. http://localhost:8888
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        Self::with_config(base_url, ClientConfig::default())
+    }
+
+    pub fn with_config(base_url: &str, config: ClientConfig) -> Result<Self, ClientError> {
+        // a trailing slash makes joined paths append to the base instead of replacing its last segment
+        let base = Url::parse(&format!("{}/", base_url.trim_end_matches('/')))
+            .map_err(|e| ClientError::Url(format!("{base_url}: {e}")))?;
+        let http = reqwest::Client::builder().timeout(config.timeout).build()?;
+
+        Ok(Self { base, http, config })
+    }
+
+    // GET /ping
+    pub async fn ping(&self) -> Result<Message, ClientError> {
+        self.get_json("ping", &[]).await
+    }
+
+    // GET /metrics/orders
+    // without conversion types the api uses the configured ones, placed_order by default,
+    // without a variant the completed cycles
+    pub async fn order_metrics(&self, exclude_suspect: bool, conversion: Option<&[&str]>, variant: Option<VisitsVariant>) -> Result<Metrics, ClientError> {
+        self.get_json("metrics/orders", &metrics_query(exclude_suspect, conversion, variant)).await
+    }
+
+    // GET /metrics/orders/history
+    pub async fn order_metrics_history(&self, exclude_suspect: bool, conversion: Option<&[&str]>, variant: Option<VisitsVariant>) -> Result<Vec<MetricsSnapshot>, ClientError> {
+        self.get_json("metrics/orders/history", &metrics_query(exclude_suspect, conversion, variant)).await
+    }
+
+    // GET /metrics/orders/history?variant=legacy, the rows precomputed before the visits variants
+    pub async fn legacy_order_metrics_history(&self, exclude_suspect: bool, conversion: Option<&[&str]>) -> Result<Vec<MetricsSnapshot>, ClientError> {
+        let mut query = metrics_query(exclude_suspect, conversion, None);
+        query.push(("variant", LEGACY_VISITS_VARIANT.to_string()));
+
+        self.get_json("metrics/orders/history", &query).await
+    }
+
+    // GET /metrics/revenue
+    pub async fn revenue_metrics(&self, exclude_suspect: bool) -> Result<RevenueMetrics, ClientError> {
+        self.get_json("metrics/revenue", &[("exclude_suspect", exclude_suspect.to_string())]).await
+    }
+
+    // GET /metrics/attribution
+    pub async fn attribution_metrics(&self, exclude_suspect: bool) -> Result<AttributionMetrics, ClientError> {
+        self.get_json("metrics/attribution", &[("exclude_suspect", exclude_suspect.to_string())]).await
+    }
+
+    // GET /metrics/pool
+    pub async fn pool_metrics(&self) -> Result<PoolStats, ClientError> {
+        self.get_json("metrics/pool", &[]).await
+    }
+
+    // GET /metrics/prometheus, in the prometheus text format
+    pub async fn prometheus_metrics(&self) -> Result<String, ClientError> {
+        self.send(|| self.request(Method::GET, "metrics/prometheus", &[]), true).await
+    }
+
+    // GET /metrics
+    pub async fn list_metrics(&self) -> Result<Vec<MetricDefinition>, ClientError> {
+        self.get_json("metrics", &[]).await
+    }
+
+    // GET /data/events, needs the events:read scope
+    pub async fn view_events(&self, customer_id: Option<i64>, limit: Option<u32>, offset: Option<u32>) -> Result<Vec<Event>, ClientError> {
+        let mut query = Vec::new();
+        if let Some(customer_id) = customer_id {
+            query.push(("customer_id", customer_id.to_string()));
+        }
+        if let Some(limit) = limit {
+            query.push(("limit", limit.to_string()));
+        }
+        if let Some(offset) = offset {
+            query.push(("offset", offset.to_string()));
+        }
+
+        self.get_json("data/events", &query).await
+    }
+
+    // GET /data/attribution, needs the events:read scope
+    pub async fn view_attribution(&self, customer_id: Option<i64>, limit: Option<u32>, offset: Option<u32>) -> Result<Vec<OrderAttribution>, ClientError> {
+        let mut query = Vec::new();
+        if let Some(customer_id) = customer_id {
+            query.push(("customer_id", customer_id.to_string()));
+        }
+        if let Some(limit) = limit {
+            query.push(("limit", limit.to_string()));
+        }
+        if let Some(offset) = offset {
+            query.push(("offset", offset.to_string()));
+        }
+
+        self.get_json("data/attribution", &query).await
+    }
+
+    // POST /data/re-sessionize, needs the sessions:write scope
+    // not idempotent, only retried when the api surely didn't run it, see `send`
+    pub async fn re_sessionize(&self, session_length: u32) -> Result<Message, ClientError> {
+        let query = [("session_length", session_length.to_string())];
+        let body = self.send(|| self.request(Method::POST, "data/re-sessionize", &query), false).await?;
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    // GET /metrics/<name> with the filters the metric declares
+    pub async fn declared_metric(&self, name: &str, filters: &[(&str, String)]) -> Result<MetricResult, ClientError> {
+        self.get_json(&format!("metrics/{name}"), filters).await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T, ClientError> {
+        let body = self.send(|| self.request(Method::GET, path, query), true).await?;
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    fn request(&self, method: Method, path: &str, query: &[(&str, String)]) -> Result<RequestBuilder, ClientError> {
+        let url = self
+            .base
+            .join(path)
+            .map_err(|e| ClientError::Url(format!("{path}: {e}")))?;
+
+        let request = self.http.request(method, url).query(query);
+
+        Ok(match &self.config.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        })
+    }
+
+    // send a request, retrying transient errors with exponential backoff, and return the body
+    //
+    // a response with a Retry-After header (a 429 over the rate limit) waits as long as it asks,
+    // retrying any sooner would only draw another 429
+    //
+    // a request that isn't idempotent is only retried when it never reached a handler, after a
+    // timeout or a 5xx it may have been applied and running it again would do it twice
+    async fn send<F>(&self, build: F, idempotent: bool) -> Result<String, ClientError>
+    where
+        F: Fn() -> Result<RequestBuilder, ClientError>,
+    {
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 0;
+
+        loop {
+            match self.attempt(build()?).await {
+                Err(e) if (if idempotent { e.is_transient() } else { e.is_unsent() }) && attempt < self.config.max_retries => {
+                    let wait = e.retry_after().unwrap_or(backoff);
+                    warn!(error = %e, attempt, ?wait, "transient api error, retrying");
+
+                    sleep(wait).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn attempt(&self, request: RequestBuilder) -> Result<String, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        // only the delay in seconds, the api never sends a date
+        let retry_after = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(ClientError::Status { status, body, retry_after });
+        }
+
+        Ok(body)
+    }
+}
+
+// query of the order metrics routes
+fn metrics_query(exclude_suspect: bool, conversion: Option<&[&str]>, variant: Option<VisitsVariant>) -> Vec<(&'static str, String)> {
+    let mut query = vec![("exclude_suspect", exclude_suspect.to_string())];
+    if let Some(conversion) = conversion {
+        query.push(("conversion", conversion.join(",")));
+    }
+    if let Some(variant) = variant {
+        query.push(("variant", variant.as_str().to_string()));
+    }
+
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // an api answering with the given status and body, one response per request, recording the
+    // head of every request it got
+    async fn serve(responses: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("error binding listener");
+        let base = format!("http://{}", listener.local_addr().expect("error getting address"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.expect("error accepting");
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.expect("error reading request");
+                    if n == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buf[..n]);
+                }
+                seen.lock().unwrap().push(String::from_utf8_lossy(&head).to_string());
+
+                let response = format!("HTTP/1.1 {status} X\r\nContent-Length: {}\r\nRetry-After: 0\r\nConnection: close\r\n\r\n{body}", body.len());
+                stream.write_all(response.as_bytes()).await.expect("error writing response");
+            }
+        });
+
+        (base, requests)
+    }
+
+    fn client(base: &str) -> Client {
+        let config = ClientConfig { initial_backoff: Duration::from_millis(1), api_key: Some("key-of-the-test".to_string()), ..ClientConfig::default() };
+
+        Client::with_config(base, config).unwrap()
+    }
+
+    const PONG: &str = r#"{"message":"pong"}"#;
+
+    #[tokio::test]
+    async fn gets_are_retried_after_a_server_error() {
+        let (base, requests) = serve(vec![(503, "busy"), (500, "oops"), (200, PONG)]).await;
+
+        let message = client(&base).ping().await.unwrap();
+
+        assert_eq!(message.message, "pong");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_max_retries() {
+        let (base, requests) = serve(vec![(503, "busy"); 4]).await;
+
+        let e = client(&base).ping().await.unwrap_err();
+
+        assert_eq!(e.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(requests.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (base, requests) = serve(vec![(403, "forbidden")]).await;
+
+        let e = client(&base).ping().await.unwrap_err();
+
+        assert_eq!(e.status(), Some(StatusCode::FORBIDDEN));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn re_sessionize_is_not_retried_after_a_server_error() {
+        let (base, requests) = serve(vec![(503, "busy"), (200, PONG)]).await;
+
+        let e = client(&base).re_sessionize(45).await.unwrap_err();
+
+        assert_eq!(e.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn re_sessionize_is_retried_when_rate_limited() {
+        let (base, requests) = serve(vec![(429, "slow down"), (200, PONG)]).await;
+
+        client(&base).re_sessionize(45).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("POST /data/re-sessionize?session_length=45 "));
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_api_key_and_the_query() {
+        let (base, requests) = serve(vec![(200, "[]")]).await;
+
+        client(&format!("{base}/")).view_events(Some(7), Some(10), None).await.unwrap();
+
+        let request = requests.lock().unwrap()[0].to_lowercase();
+        assert!(request.starts_with("get /data/events?customer_id=7&limit=10 "));
+        assert!(request.contains("authorization: bearer key-of-the-test"));
+    }
+
+    #[tokio::test]
+    async fn undecodable_bodies_fail() {
+        let (base, _) = serve(vec![(200, "not json")]).await;
+
+        let e = client(&base).ping().await.unwrap_err();
+
+        assert!(matches!(e, ClientError::Decode(_)));
+    }
+
+    #[test]
+    fn debug_hides_the_api_key() {
+        let debug = format!("{:?}", client("http://localhost:8888"));
+
+        assert!(!debug.contains("key-of-the-test"));
+    }
+}
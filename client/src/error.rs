@@ -0,0 +1,84 @@
+use reqwest::StatusCode;
+use std::time::Duration;
+use thiserror::Error;
+
+// errors of a call to the api
+#[derive(Debug, Error)]
+pub enum ClientError {
+    // the base url or a path could not be turned into a url
+    #[error("invalid url: {0}")]
+    Url(String),
+    // the request never got a response: connection refused, reset, timed out
+    #[error("error sending request: {0}")]
+    Transport(#[from] reqwest::Error),
+    // the api answered with an error status, with the wait it asked for in a Retry-After header
+    #[error("api responded with {status}: {body}")]
+    Status { status: StatusCode, body: String, retry_after: Option<Duration> },
+    // the api answered with a body that doesn't match the model
+    #[error("error decoding response: {0}")]
+    Decode(#[from] serde_json::Error),
+}
+
+impl ClientError {
+    // worth retrying: the api was unreachable, overloaded or failed on its side
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Transport(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            ClientError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            ClientError::Url(_) | ClientError::Decode(_) => false,
+        }
+    }
+
+    // surely not handled by the api: no connection was made or the rate limit turned it away,
+    // safe to retry even if the request isn't idempotent
+    pub fn is_unsent(&self) -> bool {
+        match self {
+            ClientError::Transport(e) => e.is_connect(),
+            ClientError::Status { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS,
+            ClientError::Url(_) | ClientError::Decode(_) => false,
+        }
+    }
+
+    // the wait before retrying the api asked for, e.g. with a 429 over the rate limit
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ClientError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    // the status of the response, if there was one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ClientError::Status { status, .. } => Some(*status),
+            ClientError::Transport(e) => e.status(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(code: u16) -> ClientError {
+        ClientError::Status { status: StatusCode::from_u16(code).unwrap(), body: String::new(), retry_after: None }
+    }
+
+    #[test]
+    fn transient_statuses() {
+        assert!(status(429).is_transient());
+        assert!(status(502).is_transient());
+        assert!(!status(400).is_transient());
+        assert!(!status(404).is_transient());
+    }
+
+    #[test]
+    fn only_rate_limited_requests_are_surely_unsent() {
+        assert!(status(429).is_unsent());
+        assert!(!status(503).is_unsent());
+        assert!(!status(500).is_unsent());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod client;
+pub mod error;
+
+pub use client::{Client, ClientConfig};
+pub use error::ClientError;
+
+// the response models, shared with the api through webshop-core so they can't drift apart
+pub use webshop_core::definitions::{MetricDefinition, MetricResult};
+pub use webshop_core::{AttributionMetrics, ChannelAttribution, Event, Message, Metrics, MetricsSnapshot, OrderAttribution, PoolStats, RevenueBySessions, RevenueMetrics, VisitsVariant, LEGACY_VISITS_VARIANT};
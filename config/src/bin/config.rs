@@ -0,0 +1,68 @@
+use clap::{Parser, Subcommand, ValueEnum};
+
+use webshop_config::{Component, Config, ConfigArgs};
+
+// inspect the configuration the binaries would read in the current environment
+#[derive(Parser)]
+#[command(name = "config", about = "Show and check the configuration of the api and the etl")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the merged configuration as TOML, usable as a --config file
+    Print {
+        /// Replace passwords and keys with ***
+        #[arg(long)]
+        redacted: bool,
+        #[command(flatten)]
+        args: ConfigArgs,
+    },
+    /// Check that the configuration is complete for a binary
+    Check {
+        #[arg(value_enum)]
+        component: Target,
+        #[command(flatten)]
+        args: ConfigArgs,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    Api,
+    Etl,
+    Cli,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Print { redacted, args } => Config::load(&args).map(|config| {
+            if redacted {
+                print!("{}", config.redacted().to_toml());
+            } else {
+                eprintln!("# secrets are shown in plain text, use --redacted to hide them");
+                print!("{}", config.to_toml());
+            }
+        }),
+        Command::Check { component, args } => {
+            let component = match component {
+                Target::Api => Component::Api,
+                Target::Etl => Component::Etl,
+                Target::Cli => Component::Cli,
+            };
+
+            Config::load(&args)
+                .and_then(|config| config.validate(component))
+                .map(|()| println!("configuration is valid"))
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,533 @@
+use clap::Args;
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::value::Value;
+use figment::Figment;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub mod logging;
+pub mod secrets;
+
+use secrets::{lenient_string, Secret};
+
+// configuration shared by the api, the etl and their clis
+//
+// every setting is looked up in these sources, later ones win:
+//   1. the defaults below
+//   2. a TOML file, given with --config or CONFIG_FILE
+//   3. environment variables, either `WEBSHOP_<SECTION>__<KEY>` (e.g. WEBSHOP_DATABEND__HOST)
+//      or the names used before this crate existed (e.g. DATABEND_HOST), see `LEGACY_ENV`
+//   4. `--set <section>.<key>=<value>` on the command line
+//
+// secrets can also be read from files with the `*_file` settings (docker/k8s secrets)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub databend: DatabendConfig,
+    pub s3: S3Config,
+    pub session: SessionConfig,
+    pub server: ServerConfig,
+    pub etl: EtlConfig,
+}
+
+// connection to databend, used by the api and its clis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabendConfig {
+    #[serde(deserialize_with = "lenient_string")]
+    pub user: String,
+    pub password: Secret,
+    pub password_file: Option<PathBuf>,
+    pub host: String,
+    pub port: u32,
+    #[serde(deserialize_with = "lenient_string")]
+    pub database: String,
+    pub pool: PoolSettings,
+    pub stage_wait: StageWaitSettings,
+}
+
+impl Default for DatabendConfig {
+    fn default() -> Self {
+        Self {
+            user: String::new(),
+            password: Secret::default(),
+            password_file: None,
+            host: String::new(),
+            port: 8000,
+            database: String::new(),
+            pool: PoolSettings::default(),
+            stage_wait: StageWaitSettings::default(),
+        }
+    }
+}
+
+// connection pool of the api
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolSettings {
+    pub size: usize,
+    pub query_timeout_seconds: u64,
+    pub max_retries: u32,
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            size: 8,
+            query_timeout_seconds: 30,
+            max_retries: 3,
+            retry_backoff_ms: 200,
+        }
+    }
+}
+
+// how long the api waits for the etl to stage data at startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StageWaitSettings {
+    pub timeout_seconds: u64,
+    pub backoff_seconds: u64,
+    pub max_backoff_seconds: u64,
+}
+
+impl Default for StageWaitSettings {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: 600,
+            backoff_seconds: 1,
+            max_backoff_seconds: 30,
+        }
+    }
+}
+
+// the bucket the etl stages its parquet file in and the api copies it from
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub region: String,
+    #[serde(deserialize_with = "lenient_string")]
+    pub bucket: String,
+    // without keys the etl falls back to the credentials provider chain, the api needs them
+    pub access_key: Option<Secret>,
+    pub access_key_file: Option<PathBuf>,
+    pub secret_key: Option<Secret>,
+    pub secret_key_file: Option<PathBuf>,
+}
+
+// sessionization of the events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    // minutes of inactivity that end a session
+    pub length: u32,
+    // flag customers with bot or crawler like sessions
+    pub flag_suspects: bool,
+    pub suspects: SuspectSettings,
+    // event types that count as a conversion in the session metrics, the api precomputes these
+    pub conversion_types: Vec<String>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            length: 30,
+            flag_suspects: false,
+            suspects: SuspectSettings::default(),
+            conversion_types: vec!["placed_order".to_string()],
+        }
+    }
+}
+
+// thresholds of the suspect flagging
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SuspectSettings {
+    pub max_events_per_minute: f64,
+    pub min_median_gap_seconds: f64,
+    pub max_session_events: u32,
+}
+
+impl Default for SuspectSettings {
+    fn default() -> Self {
+        Self {
+            max_events_per_minute: 30.0,
+            min_median_gap_seconds: 1.0,
+            max_session_events: 1000,
+        }
+    }
+}
+
+// the http server of the api
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+    // how long computed metrics are served from the cache
+    pub cache_ttl_seconds: u64,
+    // how long a verified api key is trusted before it is looked up again
+    pub key_cache_ttl_seconds: u64,
+    // directory of .sql files overriding the embedded queries
+    pub sql_dir: Option<PathBuf>,
+    // TOML file of declared metrics replacing the embedded ones
+    pub metrics_config: Option<PathBuf>,
+    // field of the order events' attributes holding the amount of the order
+    pub order_amount_attribute: String,
+    pub rate_limit: RateLimitSettings,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".to_string(),
+            port: 8000,
+            cache_ttl_seconds: 30,
+            key_cache_ttl_seconds: 60,
+            sql_dir: None,
+            metrics_config: None,
+            order_amount_attribute: "amount".to_string(),
+            rate_limit: RateLimitSettings::default(),
+        }
+    }
+}
+
+// token bucket budgets of the api, per api key and per ip, heavy requests (/data) have their own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    pub per_key: BudgetSettings,
+    pub per_ip: BudgetSettings,
+    pub heavy_per_key: BudgetSettings,
+    pub heavy_per_ip: BudgetSettings,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            per_key: BudgetSettings { burst: 20, per_minute: 60 },
+            per_ip: BudgetSettings { burst: 40, per_minute: 120 },
+            heavy_per_key: BudgetSettings { burst: 2, per_minute: 2 },
+            heavy_per_ip: BudgetSettings { burst: 4, per_minute: 4 },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BudgetSettings {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+// the etl run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EtlConfig {
+    // where to download the events from
+    pub url: String,
+    // local path of the parquet file, also its key in the bucket
+    pub data_path: String,
+    // prometheus textfile the run metrics are written to
+    pub metrics_file: Option<String>,
+    // pushgateway the run metrics are pushed to
+    pub pushgateway_url: Option<String>,
+    // runs with fewer events than this are read and sessionized without polars
+    pub reference_below_rows: Option<usize>,
+}
+
+// environment variables from before this crate existed and the setting each of them maps to
+pub const LEGACY_ENV: &[(&str, &str)] = &[
+    ("DATABEND_USER", "databend.user"),
+    ("DATABEND_PWD", "databend.password"),
+    ("DATABEND_PWD_FILE", "databend.password_file"),
+    ("DATABEND_HOST", "databend.host"),
+    ("DATABEND_PORT", "databend.port"),
+    ("DATABEND_DB", "databend.database"),
+    ("DATABEND_POOL_SIZE", "databend.pool.size"),
+    ("DATABEND_QUERY_TIMEOUT_SECONDS", "databend.pool.query_timeout_seconds"),
+    ("DATABEND_MAX_RETRIES", "databend.pool.max_retries"),
+    ("DATABEND_RETRY_BACKOFF_MS", "databend.pool.retry_backoff_ms"),
+    ("STAGE_WAIT_TIMEOUT_SECONDS", "databend.stage_wait.timeout_seconds"),
+    ("STAGE_WAIT_BACKOFF_SECONDS", "databend.stage_wait.backoff_seconds"),
+    ("STAGE_WAIT_MAX_BACKOFF_SECONDS", "databend.stage_wait.max_backoff_seconds"),
+    ("BUCKET_ENDPOINT", "s3.endpoint"),
+    ("BUCKET_REGION", "s3.region"),
+    ("STAGING_BUCKET", "s3.bucket"),
+    ("ACCESS_KEY", "s3.access_key"),
+    ("ACCESS_KEY_FILE", "s3.access_key_file"),
+    ("SECRET_KEY", "s3.secret_key"),
+    ("SECRET_KEY_FILE", "s3.secret_key_file"),
+    ("SESSION_LENGTH", "session.length"),
+    ("FLAG_SUSPECTS", "session.flag_suspects"),
+    ("SUSPECT_MAX_EVENTS_PER_MINUTE", "session.suspects.max_events_per_minute"),
+    ("SUSPECT_MIN_MEDIAN_GAP_SECONDS", "session.suspects.min_median_gap_seconds"),
+    ("SUSPECT_MAX_SESSION_EVENTS", "session.suspects.max_session_events"),
+    ("ROCKET_ADDRESS", "server.address"),
+    ("ROCKET_PORT", "server.port"),
+    ("METRICS_CACHE_TTL_SECONDS", "server.cache_ttl_seconds"),
+    ("API_KEY_CACHE_TTL_SECONDS", "server.key_cache_ttl_seconds"),
+    ("SQL_DIR", "server.sql_dir"),
+    ("METRICS_CONFIG", "server.metrics_config"),
+    ("RATE_LIMIT_KEY_PER_MINUTE", "server.rate_limit.per_key.per_minute"),
+    ("RATE_LIMIT_KEY_BURST", "server.rate_limit.per_key.burst"),
+    ("RATE_LIMIT_IP_PER_MINUTE", "server.rate_limit.per_ip.per_minute"),
+    ("RATE_LIMIT_IP_BURST", "server.rate_limit.per_ip.burst"),
+    ("RATE_LIMIT_HEAVY_KEY_PER_MINUTE", "server.rate_limit.heavy_per_key.per_minute"),
+    ("RATE_LIMIT_HEAVY_KEY_BURST", "server.rate_limit.heavy_per_key.burst"),
+    ("RATE_LIMIT_HEAVY_IP_PER_MINUTE", "server.rate_limit.heavy_per_ip.per_minute"),
+    ("RATE_LIMIT_HEAVY_IP_BURST", "server.rate_limit.heavy_per_ip.burst"),
+    ("URL", "etl.url"),
+    ("DATA_PATH", "etl.data_path"),
+    ("METRICS_FILE", "etl.metrics_file"),
+    ("PUSHGATEWAY_URL", "etl.pushgateway_url"),
+];
+
+// the binary a config is validated for, each one needs different settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Api,
+    Etl,
+    // the admin clis of the api, they only talk to databend
+    Cli,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("error reading the configuration: {0}")]
+    Load(#[from] Box<figment::Error>),
+    #[error("invalid --set `{0}`, expected <section>.<key>=<value>")]
+    Override(String),
+    #[error("error reading {setting} from {path}: {source}")]
+    SecretFile {
+        setting: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid configuration:\n{}", .0.iter().map(|p| format!("  - {p}")).collect::<Vec<_>>().join("\n"))]
+    Invalid(Vec<String>),
+}
+
+// command line flags every binary accepts to configure itself
+#[derive(Debug, Clone, Default, Args)]
+pub struct ConfigArgs {
+    /// TOML configuration file, defaults to $CONFIG_FILE
+    #[arg(long = "config", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Override a setting, e.g. --set databend.host=localhost
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+}
+
+impl Config {
+    // layer defaults, the config file, the environment and the command line
+    pub fn load(args: &ConfigArgs) -> Result<Self, ConfigError> {
+        let file = args
+            .config
+            .clone()
+            .or_else(|| non_empty_env("CONFIG_FILE").map(PathBuf::from));
+
+        let mut figment = Figment::from(Serialized::defaults(Config::default()));
+
+        if let Some(file) = file {
+            figment = figment.merge(Toml::file(file));
+        }
+
+        for (env, key) in LEGACY_ENV {
+            if let Some(value) = non_empty_env(env) {
+                figment = figment.merge((*key, parse_value(&value)));
+            }
+        }
+
+        figment = figment.merge(Env::prefixed("WEBSHOP_").split("__"));
+
+        for set in &args.overrides {
+            let (key, value) = set
+                .split_once('=')
+                .filter(|(key, _)| !key.trim().is_empty())
+                .ok_or_else(|| ConfigError::Override(set.clone()))?;
+
+            figment = figment.merge((key.trim(), parse_value(value.trim())));
+        }
+
+        let mut config: Config = figment.extract().map_err(Box::new)?;
+        config.read_secret_files()?;
+
+        Ok(config)
+    }
+
+    // load and validate the configuration for a binary, exiting with the problems if it is invalid
+    pub fn load_for(component: Component, args: &ConfigArgs) -> Self {
+        let config = Config::load(args).and_then(|config| {
+            config.validate(component)?;
+            Ok(config)
+        });
+
+        match config {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{e}");
+                eprintln!("see `config print --redacted` for the configuration that was read");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // a `*_file` setting takes precedence over the secret itself
+    fn read_secret_files(&mut self) -> Result<(), ConfigError> {
+        if let Some(path) = &self.databend.password_file {
+            self.databend.password = read_secret("databend.password_file", path)?;
+        }
+        if let Some(path) = &self.s3.access_key_file {
+            self.s3.access_key = Some(read_secret("s3.access_key_file", path)?);
+        }
+        if let Some(path) = &self.s3.secret_key_file {
+            self.s3.secret_key = Some(read_secret("s3.secret_key_file", path)?);
+        }
+
+        // an empty secret is no secret
+        self.s3.access_key = self.s3.access_key.take().filter(|key| !key.is_empty());
+        self.s3.secret_key = self.s3.secret_key.take().filter(|key| !key.is_empty());
+
+        Ok(())
+    }
+
+    // check that everything the component needs is set and in range, reporting every problem at once
+    pub fn validate(&self, component: Component) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        let mut require = |set: bool, key: &str| {
+            if !set {
+                problems.push(missing(key));
+            }
+        };
+
+        if matches!(component, Component::Api | Component::Cli) {
+            require(!self.databend.user.is_empty(), "databend.user");
+            require(!self.databend.password.is_empty(), "databend.password");
+            require(!self.databend.host.is_empty(), "databend.host");
+            require(!self.databend.database.is_empty(), "databend.database");
+        }
+
+        if component == Component::Api {
+            require(!self.s3.endpoint.is_empty(), "s3.endpoint");
+            require(!self.s3.bucket.is_empty(), "s3.bucket");
+            require(self.s3.access_key.is_some(), "s3.access_key");
+            require(self.s3.secret_key.is_some(), "s3.secret_key");
+        }
+
+        if component == Component::Etl {
+            require(!self.etl.url.is_empty(), "etl.url");
+            require(!self.etl.data_path.is_empty(), "etl.data_path");
+            require(!self.s3.endpoint.is_empty(), "s3.endpoint");
+            require(!self.s3.region.is_empty(), "s3.region");
+            require(!self.s3.bucket.is_empty(), "s3.bucket");
+        }
+
+        if self.session.length == 0 {
+            problems.push("session.length must be at least 1 minute".to_string());
+        }
+        if self.databend.pool.size == 0 {
+            problems.push("databend.pool.size must be at least 1".to_string());
+        }
+        if self.databend.port == 0 || self.databend.port > u16::MAX as u32 {
+            problems.push(format!("databend.port {} is not a valid port", self.databend.port));
+        }
+        if self.databend.stage_wait.backoff_seconds > self.databend.stage_wait.max_backoff_seconds {
+            problems.push("databend.stage_wait.backoff_seconds is larger than databend.stage_wait.max_backoff_seconds".to_string());
+        }
+        if self.s3.access_key.is_some() != self.s3.secret_key.is_some() {
+            problems.push("s3.access_key and s3.secret_key have to be set together".to_string());
+        }
+        if !self.etl.url.is_empty() && !self.etl.url.starts_with("http://") && !self.etl.url.starts_with("https://") {
+            problems.push(format!("etl.url `{}` is not an http(s) url", self.etl.url));
+        }
+        for (name, budget) in [
+            ("per_key", self.server.rate_limit.per_key),
+            ("per_ip", self.server.rate_limit.per_ip),
+            ("heavy_per_key", self.server.rate_limit.heavy_per_key),
+            ("heavy_per_ip", self.server.rate_limit.heavy_per_ip),
+        ] {
+            if budget.burst == 0 {
+                problems.push(format!("server.rate_limit.{name}.burst must be at least 1"));
+            }
+            // an empty bucket would never refill
+            if budget.per_minute == 0 {
+                problems.push(format!("server.rate_limit.{name}.per_minute must be at least 1"));
+            }
+        }
+        // the conversion types end up in the metrics queries
+        if self.session.conversion_types.is_empty() {
+            problems.push("session.conversion_types needs at least one event type".to_string());
+        }
+        for conversion_type in &self.session.conversion_types {
+            if conversion_type.is_empty() || !conversion_type.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                problems.push(format!("session.conversion_types `{conversion_type}` may only contain letters, digits, `_` and `-`"));
+            }
+        }
+        // the attribute name ends up in the revenue queries
+        let attribute = &self.server.order_amount_attribute;
+        if attribute.is_empty() || !attribute.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            problems.push(format!("server.order_amount_attribute `{attribute}` may only contain letters, digits, `_` and `-`"));
+        }
+        for (key, path) in [("server.sql_dir", &self.server.sql_dir), ("server.metrics_config", &self.server.metrics_config)] {
+            if let Some(path) = path.as_deref().filter(|p| !p.exists()) {
+                problems.push(format!("{key} {} does not exist", path.display()));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    // the config with every secret replaced by `***`
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+
+        config.databend.password = config.databend.password.redacted();
+        config.s3.access_key = config.s3.access_key.as_ref().map(Secret::redacted);
+        config.s3.secret_key = config.s3.secret_key.as_ref().map(Secret::redacted);
+
+        config
+    }
+
+    // the config as a TOML file that `--config` accepts
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("error serializing config")
+    }
+}
+
+// how to set a missing setting, with the legacy env var if there is one
+fn missing(key: &str) -> String {
+    let native = format!("WEBSHOP_{}", key.replace('.', "__").to_uppercase());
+
+    match LEGACY_ENV.iter().find(|(_, k)| *k == key) {
+        Some((legacy, _)) => format!("{key} is not set, set it in the config file, as {legacy} or {native}, or with --set {key}=..."),
+        None => format!("{key} is not set, set it in the config file, as {native}, or with --set {key}=..."),
+    }
+}
+
+// parse a value like figment's env provider does, so `--set` and env values behave the same
+fn parse_value(value: &str) -> Value {
+    value.parse().unwrap_or_else(|_| Value::from(value.to_string()))
+}
+
+fn read_secret(setting: &str, path: &Path) -> Result<Secret, ConfigError> {
+    fs::read_to_string(path)
+        .map(Secret::new)
+        .map_err(|source| ConfigError::SecretFile {
+            setting: setting.to_string(),
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+// env vars that are unset or empty are treated as missing
+fn non_empty_env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|v| !v.is_empty())
+}
@@ -0,0 +1,29 @@
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+use crate::secrets::RedactingWriter;
+
+// the logging of both binaries: stdout, filtered by RUST_LOG (default `info`) and as JSON lines
+// when LOG_FORMAT=json, with every secret of the config redacted
+//
+// every span logs its duration when it closes, the requests and queries of the api and the
+// stages of the etl
+pub fn init_logging() {
+    let filter = std::env::var("RUST_LOG")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(EnvFilter::new)
+        .unwrap_or_else(|| EnvFilter::new("info"));
+    let json = std::env::var("LOG_FORMAT").map(|v| v == "json").unwrap_or(false);
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(|| RedactingWriter::new(std::io::stdout()));
+
+    if json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
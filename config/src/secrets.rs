@@ -0,0 +1,173 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+// every secret value read so far, replaced by `***` in anything passed through `redact`
+static REGISTERED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// shorter secrets are not redacted, they would be replaced in unrelated text too (a password of
+// `42` in every number of a log line)
+const MIN_REDACTED_LEN: usize = 6;
+
+// a credential that never shows up in Debug or Display output
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        let value = value.trim().to_string();
+
+        if value.chars().count() >= MIN_REDACTED_LEN {
+            REGISTERED.lock().expect("lock secrets").push(value.clone());
+        }
+
+        Secret(value)
+    }
+
+    // the actual value, only to be handed to the client that needs it
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // stand-in shown by `config print --redacted`
+    pub(crate) fn redacted(&self) -> Self {
+        if self.0.is_empty() {
+            Secret(String::new())
+        } else {
+            Secret("***".to_string())
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+// serializes the actual value, only `config print` serializes the config and it redacts on request
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+// env values that look like numbers arrive as numbers, a password may well be all digits
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(LenientString).map(Secret::new)
+    }
+}
+
+// deserialize a string that may have been parsed into a number or a bool
+pub(crate) fn lenient_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    deserializer.deserialize_any(LenientString)
+}
+
+struct LenientString;
+
+impl<'de> Visitor<'de> for LenientString {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+}
+
+// replace every known secret in a message
+pub fn redact(message: &str) -> String {
+    REGISTERED
+        .lock()
+        .expect("lock secrets")
+        .iter()
+        .fold(message.to_string(), |message, secret| message.replace(secret.as_str(), "***"))
+}
+
+// a log writer redacting every known secret, the logging of both binaries writes through it,
+// see `logging::init_logging`
+//
+// the formatter writes one event per writer, so the event is buffered and redacted as a whole
+pub struct RedactingWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> RedactingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, buffer: Vec::new() }
+    }
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let message = redact(&String::from_utf8_lossy(&self.buffer));
+            self.buffer.clear();
+            self.inner.write_all(message.as_bytes())?;
+        }
+
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RedactingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_secrets_are_redacted() {
+        let _secret = Secret::new(" s3cr3t-value\n".to_string());
+
+        assert_eq!(redact("password=s3cr3t-value;"), "password=***;");
+    }
+
+    #[test]
+    fn short_secrets_are_not_redacted() {
+        let _secret = Secret::new("42".to_string());
+
+        assert_eq!(redact("took 42 ms, 420 rows"), "took 42 ms, 420 rows");
+    }
+}
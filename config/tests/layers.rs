@@ -0,0 +1,194 @@
+// loading and validation of the config, run in a figment jail so the env vars and files of one
+// test don't leak into the others
+
+use figment::Jail;
+use std::path::PathBuf;
+
+use webshop_config::{Component, Config, ConfigArgs, ConfigError};
+
+fn args(file: Option<&str>, overrides: &[&str]) -> ConfigArgs {
+    ConfigArgs {
+        config: file.map(PathBuf::from),
+        overrides: overrides.iter().map(|o| o.to_string()).collect(),
+    }
+}
+
+// defaults < file < env < --set
+#[test]
+fn later_layers_win() {
+    Jail::expect_with(|jail| {
+        jail.create_file(
+            "config.toml",
+            r#"
+            [databend]
+            host = "file-host"
+            port = 1
+
+            [session]
+            length = 10
+            "#,
+        )?;
+        jail.set_env("WEBSHOP_DATABEND__PORT", "2");
+        jail.set_env("WEBSHOP_SESSION__LENGTH", "20");
+
+        let config = Config::load(&args(Some("config.toml"), &["session.length=5"])).expect("error loading config");
+
+        assert_eq!(config.session.length, 5);
+        assert_eq!(config.databend.port, 2);
+        assert_eq!(config.databend.host, "file-host");
+        assert_eq!(config.databend.pool.size, 8);
+
+        Ok(())
+    });
+}
+
+// the native env vars win over the legacy ones, the legacy ones over the file
+#[test]
+fn native_env_wins_over_legacy_env() {
+    Jail::expect_with(|jail| {
+        jail.create_file("config.toml", "[session]\nlength = 10\nflag_suspects = false\n")?;
+        jail.set_env("SESSION_LENGTH", "11");
+        jail.set_env("WEBSHOP_SESSION__LENGTH", "12");
+        jail.set_env("FLAG_SUSPECTS", "true");
+
+        let config = Config::load(&args(Some("config.toml"), &[])).expect("error loading config");
+
+        assert_eq!(config.session.length, 12);
+        assert!(config.session.flag_suspects);
+
+        Ok(())
+    });
+}
+
+#[test]
+fn legacy_env_mappings() {
+    Jail::expect_with(|jail| {
+        jail.set_env("DATABEND_USER", "webshop");
+        // all digits, parsed as a number on the way in
+        jail.set_env("DATABEND_PWD", "1234");
+        jail.set_env("DATABEND_HOST", "databend");
+        jail.set_env("DATABEND_DB", "webshop");
+        jail.set_env("DATABEND_POOL_SIZE", "3");
+        jail.set_env("STAGING_BUCKET", "staging");
+        jail.set_env("ACCESS_KEY", "access");
+        jail.set_env("ROCKET_PORT", "8888");
+        jail.set_env("RATE_LIMIT_HEAVY_IP_BURST", "7");
+        jail.set_env("URL", "http://localhost/events.jsonl");
+
+        let config = Config::load(&args(None, &[])).expect("error loading config");
+
+        assert_eq!(config.databend.user, "webshop");
+        assert_eq!(config.databend.password.expose(), "1234");
+        assert_eq!(config.databend.host, "databend");
+        assert_eq!(config.databend.database, "webshop");
+        assert_eq!(config.databend.pool.size, 3);
+        assert_eq!(config.s3.bucket, "staging");
+        assert_eq!(config.s3.access_key.as_ref().map(|k| k.expose()), Some("access"));
+        assert_eq!(config.server.port, 8888);
+        assert_eq!(config.server.rate_limit.heavy_per_ip.burst, 7);
+        assert_eq!(config.etl.url, "http://localhost/events.jsonl");
+
+        Ok(())
+    });
+}
+
+// the *_file settings replace the secret itself, trimmed of the trailing newline
+#[test]
+fn secrets_from_files() {
+    Jail::expect_with(|jail| {
+        jail.create_file("password", "from-file\n")?;
+        jail.create_file("secret_key", "secret-from-file\n")?;
+        jail.set_env("DATABEND_PWD", "from-env");
+        jail.set_env("DATABEND_PWD_FILE", "password");
+        jail.set_env("WEBSHOP_S3__SECRET_KEY_FILE", "secret_key");
+
+        let config = Config::load(&args(None, &[])).expect("error loading config");
+
+        assert_eq!(config.databend.password.expose(), "from-file");
+        assert_eq!(config.s3.secret_key.as_ref().map(|k| k.expose()), Some("secret-from-file"));
+
+        Ok(())
+    });
+}
+
+#[test]
+fn missing_secret_file() {
+    Jail::expect_with(|jail| {
+        jail.set_env("DATABEND_PWD_FILE", "does-not-exist");
+
+        let error = Config::load(&args(None, &[])).expect_err("a missing secret file is an error");
+        assert!(matches!(error, ConfigError::SecretFile { ref setting, .. } if setting == "databend.password_file"));
+
+        Ok(())
+    });
+}
+
+#[test]
+fn malformed_override() {
+    Jail::expect_with(|_| {
+        let error = Config::load(&args(None, &["session.length"])).expect_err("an override needs a value");
+        assert!(matches!(error, ConfigError::Override(_)));
+
+        Ok(())
+    });
+}
+
+// validation reports every problem at once instead of the first one
+#[test]
+fn validation_lists_every_problem() {
+    let mut config = Config::default();
+    config.session.length = 0;
+    config.etl.url = "ftp://events".to_string();
+
+    let Err(ConfigError::Invalid(problems)) = config.validate(Component::Api) else {
+        panic!("an empty config is invalid for the api");
+    };
+
+    for key in [
+        "databend.user",
+        "databend.password",
+        "databend.host",
+        "databend.database",
+        "s3.endpoint",
+        "s3.bucket",
+        "s3.access_key",
+        "s3.secret_key",
+        "session.length",
+        "etl.url",
+    ] {
+        assert!(problems.iter().any(|p| p.starts_with(key)), "no problem with {key} in {problems:?}");
+    }
+    assert_eq!(problems.len(), 10, "{problems:?}");
+
+    // missing settings name the env vars that set them
+    assert!(problems.iter().any(|p| p.contains("DATABEND_HOST") && p.contains("WEBSHOP_DATABEND__HOST")));
+}
+
+// each binary only needs its own settings
+#[test]
+fn validation_depends_on_the_component() {
+    let mut config = Config::default();
+    config.etl.url = "http://localhost/events.jsonl".to_string();
+    config.etl.data_path = "events.parquet".to_string();
+    config.s3.endpoint = "http://localhost:9000".to_string();
+    config.s3.region = "us-east-1".to_string();
+    config.s3.bucket = "staging".to_string();
+
+    assert!(config.validate(Component::Etl).is_ok());
+    assert!(config.validate(Component::Cli).is_err());
+}
+
+// a budget without burst or refill would turn away every request for good
+#[test]
+fn rate_limit_budgets_are_validated() {
+    let mut config = Config::default();
+    config.server.rate_limit.per_ip.burst = 0;
+    config.server.rate_limit.heavy_per_key.per_minute = 0;
+
+    let Err(ConfigError::Invalid(problems)) = config.validate(Component::Etl) else {
+        panic!("empty rate limit budgets are invalid");
+    };
+
+    assert!(problems.iter().any(|p| p == "server.rate_limit.per_ip.burst must be at least 1"), "{problems:?}");
+    assert!(problems.iter().any(|p| p == "server.rate_limit.heavy_per_key.per_minute must be at least 1"), "{problems:?}");
+}
@@ -0,0 +1,9 @@
+-- create the table of sessionized events
+CREATE TABLE IF NOT EXISTS webshop.events (
+    customer_id int,
+    timestamp timestamp,
+    time_diff int,
+    new_session int,
+    session_number int,
+    type varchar
+);
@@ -0,0 +1,9 @@
+-- create the table of precomputed metrics, one row per refresh
+CREATE TABLE IF NOT EXISTS webshop.metrics_daily (
+    metric_date date,
+    session_length int,
+    exclude_suspect int,
+    median_visits_before_order double,
+    median_session_duration_minutes_before_order double,
+    refreshed_at timestamp
+);
@@ -0,0 +1,9 @@
+-- create the table of api keys, only the sha256 hash of a key is stored
+CREATE TABLE IF NOT EXISTS webshop.api_keys (
+    id varchar,
+    name varchar,
+    key_hash varchar,
+    scopes varchar,
+    created_at timestamp,
+    revoked_at timestamp NULL
+);
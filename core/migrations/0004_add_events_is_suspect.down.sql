@@ -0,0 +1 @@
+ALTER TABLE webshop.events DROP COLUMN is_suspect;
@@ -0,0 +1,3 @@
+-- flag the events of suspect (bot) customers, tables created before the flagging have no such
+-- column and their events count as not suspect
+ALTER TABLE webshop.events ADD COLUMN is_suspect int DEFAULT 0;
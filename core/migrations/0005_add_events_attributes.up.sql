@@ -0,0 +1,2 @@
+-- keep the fields of an event beyond the core ones, events loaded before have none
+ALTER TABLE webshop.events ADD COLUMN attributes variant;
@@ -0,0 +1,15 @@
+-- create the table of orders attributed to the sessions and channels leading to them,
+-- rebuilt from webshop.events after every load and re-sessionization
+CREATE TABLE IF NOT EXISTS webshop.order_attribution (
+    customer_id int,
+    order_timestamp timestamp,
+    order_number int,
+    converting_session int,
+    first_session int,
+    sessions_before_order int,
+    first_touch_channel varchar NULL,
+    last_touch_channel varchar NULL,
+    amount double NULL,
+    is_suspect int,
+    refreshed_at timestamp
+);
@@ -0,0 +1 @@
+ALTER TABLE webshop.metrics_daily DROP COLUMN conversion_types;
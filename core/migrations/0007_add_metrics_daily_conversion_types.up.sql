@@ -0,0 +1,3 @@
+-- tag the precomputed metrics with the event types counted as a conversion, rows before were
+-- computed for placed_order
+ALTER TABLE webshop.metrics_daily ADD COLUMN conversion_types varchar DEFAULT 'placed_order';
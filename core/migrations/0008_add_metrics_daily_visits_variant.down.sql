@@ -0,0 +1 @@
+ALTER TABLE webshop.metrics_daily DROP COLUMN visits_variant;
@@ -0,0 +1,3 @@
+-- tag the precomputed metrics with the variant of median_visits_before_order, rows before were
+-- computed with the cycles of the original query, which match none of the variants
+ALTER TABLE webshop.metrics_daily ADD COLUMN visits_variant varchar DEFAULT 'legacy';
@@ -0,0 +1,8 @@
+-- create the named connection holding the credentials of the staging bucket, replaced on every
+-- start so rotated keys take effect
+-- @skip-validation
+CREATE OR REPLACE CONNECTION staging_bucket
+    STORAGE_TYPE = 's3'
+    ENDPOINT_URL = '{endpoint}'
+    ACCESS_KEY_ID = '{access_key}'
+    SECRET_ACCESS_KEY = '{secret_key}';
@@ -0,0 +1,2 @@
+-- create the database
+CREATE DATABASE IF NOT EXISTS webshop;
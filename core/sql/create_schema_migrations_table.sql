@@ -0,0 +1,7 @@
+-- create the table of applied migrations, the migrations themselves can't create it
+CREATE TABLE IF NOT EXISTS webshop.schema_migrations (
+    version int,
+    name varchar,
+    checksum varchar,
+    applied_at timestamp
+);
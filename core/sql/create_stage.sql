@@ -0,0 +1,6 @@
+-- create the stage for the staged data, the credentials live in the named connection and the
+-- stage is replaced with it so it picks up rotated keys
+-- @skip-validation
+CREATE OR REPLACE STAGE sessionized
+    URL='s3://{bucket}/'
+    CONNECTION = (CONNECTION_NAME = 'staging_bucket');
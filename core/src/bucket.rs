@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Result};
+use s3::creds::Credentials;
+use s3::Bucket;
+use tracing::info;
+
+use webshop_config::secrets::Secret;
+
+// the staging bucket, addressed path style as minio expects
+pub async fn connect_to_bucket(region: &str, endpoint: &str, access_key: Option<&Secret>, secret_key: Option<&Secret>, bucket: &str) -> Result<Bucket> {
+    let bucket = Bucket::new(
+        bucket,
+        s3::Region::Custom {
+            region: region.to_owned(),
+            endpoint: endpoint.to_owned(),
+        },
+        get_credentials(access_key, secret_key).await?,
+    )
+    .map_err(|e| anyhow!("error making bucket: {}", e))?
+    .with_path_style();
+
+    Ok(bucket)
+}
+
+// without an access and secret key the credentials come from the provider chain
+// (AWS_* env vars, the shared credentials profile or the instance metadata)
+pub async fn get_credentials(access_key: Option<&Secret>, secret_key: Option<&Secret>) -> Result<Credentials> {
+    if access_key.is_none() || secret_key.is_none() {
+        info!("no bucket credentials configured, using the credentials provider chain");
+    }
+
+    let creds = Credentials::new(access_key.map(Secret::expose), secret_key.map(Secret::expose), None, None, None)
+        .map_err(|e| anyhow!("error constructing creds: {}", e))?;
+
+    Ok(creds)
+}
@@ -0,0 +1,23 @@
+// embed a DDL file from the sql directory under its file name
+macro_rules! embed {
+    ($name:literal) => {
+        ($name, include_str!(concat!("../sql/", $name, ".sql")))
+    };
+}
+
+// the setup outside of the versioned migrations, in the order it has to run: the database
+// and the table recording the migrations, then the connection and stage of the bucket,
+// which carry the credentials of the running deployment
+//
+// tables are created and changed by `crate::migrations`
+const EMBEDDED: &[(&str, &str)] = &[
+    embed!("create_database"),
+    embed!("create_schema_migrations_table"),
+    embed!("create_connection"),
+    embed!("create_stage"),
+];
+
+// every DDL query by name
+pub fn ddl() -> Vec<(&'static str, String)> {
+    EMBEDDED.iter().map(|(name, sql)| (*name, sql.to_string())).collect()
+}
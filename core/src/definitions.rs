@@ -0,0 +1,261 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// type of a filter value or an output column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+// a query parameter that restricts the rows a metric is computed over
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FilterDefinition {
+    pub name: String,
+    // column of the events the filter applies to, defaults to the filter name
+    pub column: Option<String>,
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    // comparison operator, defaults to equality
+    #[serde(default = "default_op")]
+    pub op: String,
+}
+
+// comparison operators a filter may use
+const OPERATORS: &[&str] = &["=", "!=", "<", "<=", ">", ">="];
+
+fn default_op() -> String {
+    "=".to_string()
+}
+
+// a column of the metric result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OutputDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+}
+
+// a metric served at /metrics/<name>
+//
+// the rows come from exactly one of:
+//   query      a query of the registry
+//   sql        inline sql
+//   aggregate  an aggregation over the events, grouped by the `group_by` columns
+//
+// the sql may use `{events}` for the events table and `{filters}` for the where clause built from the filters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MetricDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub query: Option<String>,
+    pub sql: Option<String>,
+    pub aggregate: Option<String>,
+    #[serde(default)]
+    pub group_by: Vec<String>,
+    #[serde(default, rename = "filter")]
+    pub filters: Vec<FilterDefinition>,
+    #[serde(rename = "output")]
+    pub outputs: Vec<OutputDefinition>,
+}
+
+impl MetricDefinition {
+    // name of the registry query the metric runs
+    pub fn query_name(&self) -> String {
+        self.query.clone().unwrap_or_else(|| format!("metric_{}", self.name))
+    }
+
+    // the sql of a metric that is not a registry query
+    pub fn inline_sql(&self) -> Option<String> {
+        if let Some(sql) = &self.sql {
+            return Some(sql.clone());
+        }
+
+        self.aggregate.as_ref().map(|aggregate| {
+            let mut columns = self.group_by.clone();
+            columns.push(format!("{} as {}", aggregate, self.outputs.last().map(|o| o.name.as_str()).unwrap_or("value")));
+
+            let group_by = if self.group_by.is_empty() {
+                String::new()
+            } else {
+                format!("\ngroup by {0}\norder by {0}", self.group_by.join(", "))
+            };
+
+            format!("select {}\nfrom {{events}}\nwhere {{filters}}{};", columns.join(", "), group_by)
+        })
+    }
+
+    // build the where clause from the query parameters, rejecting filters the metric doesn't allow
+    pub fn where_clause(&self, params: &HashMap<String, String>) -> Result<String> {
+        let mut conditions = vec!["1 = 1".to_string()];
+
+        let mut keys: Vec<&String> = params.keys().collect();
+        keys.sort();
+
+        for key in keys {
+            let filter = self
+                .filters
+                .iter()
+                .find(|f| &f.name == key)
+                .ok_or_else(|| anyhow!("metric `{}` has no filter `{}`", self.name, key))?;
+            let column = filter.column.as_deref().unwrap_or(&filter.name);
+
+            conditions.push(format!("{} {} {}", column, filter.op, literal(filter.value_type, &params[key])?));
+        }
+
+        Ok(conditions.join(" and "))
+    }
+
+    // reject definitions the api couldn't serve
+    pub fn check(&self) -> Result<()> {
+        let sources = [self.query.is_some(), self.sql.is_some(), self.aggregate.is_some()];
+
+        if sources.iter().filter(|s| **s).count() != 1 {
+            bail!("metric `{}` needs exactly one of query, sql or aggregate", self.name);
+        }
+        if self.outputs.is_empty() {
+            bail!("metric `{}` has no output columns", self.name);
+        }
+        if let Some(filter) = self.filters.iter().find(|f| !OPERATORS.contains(&f.op.as_str())) {
+            bail!("filter `{}` of metric `{}` has unsupported operator `{}`", filter.name, self.name, filter.op);
+        }
+
+        Ok(())
+    }
+}
+
+// render a query parameter as a sql literal of the declared type
+fn literal(value_type: ValueType, value: &str) -> Result<String> {
+    let literal = match value_type {
+        ValueType::String => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''")),
+        ValueType::Int => value.parse::<i64>().with_context(|| format!("`{}` is not an int", value))?.to_string(),
+        // NaN and inf parse as floats but aren't sql literals
+        ValueType::Float => match value.parse::<f64>() {
+            Ok(float) if float.is_finite() => float.to_string(),
+            _ => bail!("`{}` is not a float", value),
+        },
+        ValueType::Bool => (value.parse::<bool>().with_context(|| format!("`{}` is not a bool", value))? as u8).to_string(),
+    };
+
+    Ok(literal)
+}
+
+// result of a declared metric, one JSON object per row keyed by the output column names
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MetricResult {
+    pub name: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<Object>))]
+    pub rows: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(name: &str, value_type: ValueType) -> FilterDefinition {
+        FilterDefinition {
+            name: name.to_string(),
+            column: None,
+            value_type,
+            op: default_op(),
+        }
+    }
+
+    fn metric(filters: Vec<FilterDefinition>) -> MetricDefinition {
+        MetricDefinition {
+            name: "events".to_string(),
+            description: String::new(),
+            query: None,
+            sql: None,
+            aggregate: Some("count(*)".to_string()),
+            group_by: Vec::new(),
+            filters,
+            outputs: vec![OutputDefinition {
+                name: "events".to_string(),
+                value_type: ValueType::Int,
+            }],
+        }
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn where_clause_of_typed_filters() {
+        let price = FilterDefinition {
+            column: Some("price".to_string()),
+            op: ">=".to_string(),
+            ..filter("min_price", ValueType::Float)
+        };
+        let metric = metric(vec![
+            filter("customer_id", ValueType::Int),
+            filter("type", ValueType::String),
+            filter("is_suspect", ValueType::Bool),
+            price,
+        ]);
+
+        let clause = metric
+            .where_clause(&params(&[("type", "page_view"), ("customer_id", "42"), ("is_suspect", "false"), ("min_price", "2.5")]))
+            .unwrap();
+
+        assert_eq!(clause, "1 = 1 and customer_id = 42 and is_suspect = 0 and price >= 2.5 and type = 'page_view'");
+        assert_eq!(metric.where_clause(&HashMap::new()).unwrap(), "1 = 1");
+    }
+
+    #[test]
+    fn where_clause_rejects_unknown_filters() {
+        let error = metric(vec![filter("customer_id", ValueType::Int)])
+            .where_clause(&params(&[("1 = 1 or customer_id", "1")]))
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "metric `events` has no filter `1 = 1 or customer_id`");
+    }
+
+    #[test]
+    fn string_literals_are_escaped() {
+        assert_eq!(literal(ValueType::String, "it's").unwrap(), "'it''s'");
+        // a backslash can't escape the closing quote
+        assert_eq!(literal(ValueType::String, r"\' or 1 = 1 --").unwrap(), r"'\\'' or 1 = 1 --'");
+        assert_eq!(literal(ValueType::String, r"a\b").unwrap(), r"'a\\b'");
+    }
+
+    #[test]
+    fn literals_of_the_wrong_type_fail() {
+        for (value_type, value) in [
+            (ValueType::Int, "1 or 1 = 1"),
+            (ValueType::Int, "4.5"),
+            (ValueType::Float, "NaN"),
+            (ValueType::Float, "inf"),
+            (ValueType::Float, "1e400"),
+            (ValueType::Bool, "1"),
+            (ValueType::Bool, "yes"),
+        ] {
+            assert!(literal(value_type, value).is_err(), "{:?} {}", value_type, value);
+        }
+
+        assert_eq!(literal(ValueType::Int, "-7").unwrap(), "-7");
+        assert_eq!(literal(ValueType::Bool, "true").unwrap(), "1");
+    }
+
+    #[test]
+    fn unknown_operators_are_rejected() {
+        let like = FilterDefinition {
+            op: "like".to_string(),
+            ..filter("type", ValueType::String)
+        };
+
+        assert!(metric(vec![filter("type", ValueType::String)]).check().is_ok());
+        assert!(metric(vec![like]).check().is_err());
+    }
+}
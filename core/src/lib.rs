@@ -0,0 +1,11 @@
+#[cfg(feature = "bucket")]
+pub mod bucket;
+pub mod ddl;
+pub mod definitions;
+pub mod migrations;
+pub mod models;
+pub mod schema;
+
+pub use models::{AttributionMetrics, ChannelAttribution, Event, Message, Metrics, MetricsSnapshot, OrderAttribution, PoolStats, RevenueBySessions, RevenueMetrics, VisitsVariant, LEGACY_VISITS_VARIANT};
+#[cfg(feature = "bucket")]
+pub use webshop_config::secrets;
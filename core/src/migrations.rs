@@ -0,0 +1,100 @@
+// versioned schema changes of the webshop database
+//
+// every migration is a pair of files in `core/migrations`, `<version>_<name>.up.sql` and
+// `<version>_<name>.down.sql`, holding one or more statements separated by `;`. applied
+// migrations are recorded in webshop.schema_migrations with a checksum of their up file,
+// so a migration edited after it ran can be told apart. an applied migration is never
+// changed, a schema change is always a new migration with the next version
+//
+// the first migrations use `IF NOT EXISTS` and create the tables as they were before the
+// migrations existed, so older databases are adopted as they are and every later column is
+// added by a migration of its own
+
+// embed the up and down files of a migration
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+// every migration, in version order
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_create_events_table"),
+    migration!(2, "0002_create_metrics_daily_table"),
+    migration!(3, "0003_create_api_keys_table"),
+    migration!(4, "0004_add_events_is_suspect"),
+    migration!(5, "0005_add_events_attributes"),
+    migration!(6, "0006_create_order_attribution_table"),
+    migration!(7, "0007_add_metrics_daily_conversion_types"),
+    migration!(8, "0008_add_metrics_daily_visits_variant"),
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: u32,
+    // file name without the direction, e.g. 0001_create_events_table
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+impl Migration {
+    pub fn up_statements(&self) -> Vec<String> {
+        statements(self.up)
+    }
+
+    pub fn down_statements(&self) -> Vec<String> {
+        statements(self.down)
+    }
+
+    // fnv-1a of the up file, stable across builds and rust versions unlike the std hasher
+    pub fn checksum(&self) -> String {
+        let hash = self
+            .up
+            .bytes()
+            .fold(0xcbf29ce484222325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+
+        format!("{:016x}", hash)
+    }
+}
+
+// the migration with the given version
+pub fn find(version: u32) -> Option<&'static Migration> {
+    MIGRATIONS.iter().find(|m| m.version == version)
+}
+
+// the statements of a migration file, without comments, split on the `;` ending a line
+//
+// databend runs one statement per request; a `;` inside a string literal at the end of a
+// line would split the statement, so migrations avoid that
+pub fn statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+
+    for line in sql.lines() {
+        let line = line.trim_end();
+
+        if line.trim_start().starts_with("--") || line.trim().is_empty() {
+            continue;
+        }
+
+        current.push_str(line);
+        current.push('\n');
+
+        if line.ends_with(';') {
+            statements.push(current.trim().trim_end_matches(';').to_string());
+            current.clear();
+        }
+    }
+
+    if !current.trim().is_empty() {
+        statements.push(current.trim().to_string());
+    }
+
+    statements
+}
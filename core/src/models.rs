@@ -0,0 +1,208 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+// sessions before an order, as reported by the revenue, the attribution and the median visits:
+// the sessions from the one after the session of the customer's previous order (the first
+// session of the customer for a first order) up to and including the session of the order.
+// sessions are numbered from 0, so an order in session 5 after an order in session 3 took 2
+// sessions, and an order in the same session as the previous one counts as 1
+
+// object for viewing metrics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Metrics {
+    // median sessions per conversion cycle, which cycles depends on the requested variant
+    pub median_visits_before_order: f64,
+    // median duration of the sessions before the first conversion of a customer
+    pub median_session_duration_minutes_before_order: f64,
+}
+
+// object for viewing a sessionized event, a row of webshop.events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Event {
+    pub customer_id: i64,
+    pub timestamp: String,
+    pub time_diff: i64,
+    pub new_session: i64,
+    pub session_number: i64,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub is_suspect: i64,
+    // the fields of the source event beyond the ones above, null for events loaded without them
+    #[serde(default, deserialize_with = "json_object")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    pub attributes: Value,
+}
+
+// databend hands out a variant as its json text, the api hands it out as json
+fn json_object<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(text) => serde_json::from_str(&text).map_err(serde::de::Error::custom),
+        value => Ok(value),
+    }
+}
+
+// object for viewing the revenue of the placed orders
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RevenueMetrics {
+    pub total_revenue: f64,
+    pub orders: i64,
+    // orders whose attributes carry no amount, counted as orders but not as revenue
+    pub orders_without_amount: i64,
+    pub average_order_value: f64,
+    // revenue over every session, ordering or not
+    pub revenue_per_session: f64,
+    // revenue over every customer, ordering or not
+    pub revenue_per_customer: f64,
+    #[serde(default)]
+    pub by_sessions_before_order: Vec<RevenueBySessions>,
+}
+
+// revenue of the orders that took the same number of sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RevenueBySessions {
+    // see the sessions before an order at the top
+    pub sessions_before_order: i64,
+    pub orders: i64,
+    pub revenue: f64,
+    pub average_order_value: f64,
+    // revenue over the sessions the orders of the bucket took
+    pub revenue_per_session: f64,
+    // revenue over the customers with an order in the bucket
+    pub revenue_per_customer: f64,
+}
+
+// object for viewing a placed order attributed to the journey leading to it, a row of
+// webshop.order_attribution
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OrderAttribution {
+    pub customer_id: i64,
+    pub order_timestamp: String,
+    // the how many-th order of the customer, from 1
+    pub order_number: i64,
+    // the session the order was placed in
+    pub converting_session: i64,
+    // the session after the session of the previous order, the converting session for an order
+    // in the same session as the previous one
+    pub first_session: i64,
+    // sessions from the first to the converting one, both included, see the sessions before an
+    // order at the top
+    pub sessions_before_order: i64,
+    // utm_source or else referrer of the first and last event of the journey carrying one
+    pub first_touch_channel: Option<String>,
+    pub last_touch_channel: Option<String>,
+    pub amount: Option<f64>,
+    pub is_suspect: i64,
+}
+
+// orders and revenue credited to a channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ChannelAttribution {
+    // `none` for orders whose journey carried no channel
+    pub channel: String,
+    pub orders: i64,
+    pub revenue: f64,
+    pub average_sessions_before_order: f64,
+}
+
+// object for viewing the attributed orders by first-touch and by last-touch channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AttributionMetrics {
+    pub first_touch: Vec<ChannelAttribution>,
+    pub last_touch: Vec<ChannelAttribution>,
+}
+
+// object for returning messages
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Message {
+    pub message: String,
+}
+
+impl Message {
+    pub async fn message(message: String) -> Result<Message> {
+        Ok(Message { message })
+    }
+}
+
+// object for viewing a precomputed metrics row
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MetricsSnapshot {
+    pub metric_date: String,
+    pub session_length: i32,
+    // comma separated event types counted as a conversion
+    pub conversion_types: String,
+    // variant of median_visits_before_order, `LEGACY_VISITS_VARIANT` for rows computed before the variants
+    pub visits_variant: String,
+    pub median_visits_before_order: f64,
+    pub median_session_duration_minutes_before_order: f64,
+    pub refreshed_at: String,
+}
+
+// the visits variant of the metrics_daily rows precomputed before the variants existed, with the
+// median of that time; only the history serves them
+pub const LEGACY_VISITS_VARIANT: &str = "legacy";
+
+// which conversion cycles `median_visits_before_order` is the median of, see its query
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VisitsVariant {
+    // every cycle that ended in a conversion
+    #[default]
+    CompletedCycles,
+    // the sessions up to the first conversion of every converting customer
+    FirstOrder,
+    // the completed cycles and the sessions after the last conversion, customers who never
+    // converted included
+    AllCycles,
+}
+
+impl VisitsVariant {
+    pub const ALL: [VisitsVariant; 3] = [VisitsVariant::CompletedCycles, VisitsVariant::FirstOrder, VisitsVariant::AllCycles];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VisitsVariant::CompletedCycles => "completed_cycles",
+            VisitsVariant::FirstOrder => "first_order",
+            VisitsVariant::AllCycles => "all_cycles",
+        }
+    }
+
+    pub fn parse(variant: &str) -> Result<Self> {
+        match VisitsVariant::ALL.iter().find(|v| v.as_str() == variant.trim()) {
+            Some(variant) => Ok(*variant),
+            None => bail!("unknown variant `{}`, expected one of completed_cycles, first_order, all_cycles", variant),
+        }
+    }
+
+    // the kinds of cycles of the variant, for the `cycles` parameter of the query
+    pub fn cycles(&self) -> &'static str {
+        match self {
+            VisitsVariant::CompletedCycles => "'first', 'completed'",
+            VisitsVariant::FirstOrder => "'first'",
+            VisitsVariant::AllCycles => "'first', 'completed', 'open'",
+        }
+    }
+}
+
+// object for viewing the usage of the connection pool of the api
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PoolStats {
+    pub size: usize,
+    pub in_use: usize,
+    pub idle: usize,
+    pub checkouts: u64,
+    pub average_wait_ms: f64,
+    pub timeouts: u64,
+    pub retries: u64,
+    pub failures: u64,
+    pub reconnects: u64,
+}
@@ -0,0 +1,41 @@
+// the columns of webshop.events, in the order of the table
+//
+// the etl writes its parquet file in this order and the api copies it into the table with
+// `SELECT *`, so the copy is positional: this list is the one definition both sides share.
+// the migrations in `core/migrations` have to leave the table with exactly these columns,
+// the api checks that at startup
+pub const EVENT_COLUMNS: &[Column] = &[
+    Column::new("customer_id", "int"),
+    Column::new("timestamp", "timestamp"),
+    Column::new("time_diff", "int"),
+    Column::new("new_session", "int"),
+    Column::new("session_number", "int"),
+    Column::new("type", "varchar"),
+    Column::new("is_suspect", "int"),
+    // every field of the source event without a column of its own, as a json object
+    Column::new("attributes", "variant"),
+];
+
+// a column of the events table
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    // name in databend, snake case
+    pub name: &'static str,
+    pub sql_type: &'static str,
+}
+
+impl Column {
+    pub const fn new(name: &'static str, sql_type: &'static str) -> Self {
+        Self { name, sql_type }
+    }
+
+    // name of the column in the polars frames of the etl, kebab case
+    pub fn frame_name(&self) -> String {
+        self.name.replace('_', "-")
+    }
+}
+
+// the names the etl has to select, in table order
+pub fn frame_columns() -> Vec<String> {
+    EVENT_COLUMNS.iter().map(Column::frame_name).collect()
+}
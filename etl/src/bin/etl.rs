@@ -1,32 +1,69 @@
+use clap::Parser;
 use std::io::Result;
 
-use etl::etl::Data;
+use etl::etl::{Data, SuspectThresholds};
+use tracing::{error, warn};
+use webshop_config::logging::init_logging;
+use webshop_config::{Component, Config, ConfigArgs};
+
+#[derive(Parser)]
+#[command(name = "etl", about = "Sessionize the webshop events and stage them for the api")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Check the sessions against the reference sessionizer before loading them, failing the run if they differ
+    #[arg(long)]
+    verify: bool,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // load environment variables
-    // dotenvy::dotenv().expect("error loading .env vars");
+    // defaults < config file < env < command line, exits with every problem if incomplete
+    let cli = Cli::parse();
+    let config = Config::load_for(Component::Etl, &cli.config);
+
+    // structured logging, configured by RUST_LOG and LOG_FORMAT
+    init_logging();
+
+    let s3 = &config.s3;
+    let session = &config.session;
+
+    // optional flagging of suspicious (bot/crawler) sessions
+    let suspects = session
+        .flag_suspects
+        .then(|| SuspectThresholds::from(&session.suspects));
+
     // run etl
-    // get URL & SESSION_LENGTH env vars
-    let url = std::env::var("URL").expect("error getting URL");
-    let session_length: u32 = std::env::var("SESSION_LENGTH")
-        .expect("error getting SESSION_LENGTH")
-        .parse::<u32>()
-        .expect("error parsing SESSION_LENGTH into u32");
-    let data_path = std::env::var("DATA_PATH").expect("error getting DATA_PATH");
-
-    // env vars for accessiing minio
-    let region = std::env::var("BUCKET_REGION").expect("error getting BUCKET_REGION");
-    let endpoint = std::env::var("BUCKET_ENDPOINT").expect("error getting BUCKET_ENDPOINT");
-    let access_key = std::env::var("ACCESS_KEY").expect("error getting ACCESS_KEY");
-    let secret_key = std::env::var("SECRET_KEY").expect("error getting SECRET_KEY");
-    let bucket = std::env::var("STAGING_BUCKET").expect("error getting bucket name");
-
-    Data::init().await.expect("error initializing data")
-        .extract(&url).await.expect("error extracting data")
-        .transform(session_length).await.expect("error transforming data")
-        .load(&data_path, &region, &endpoint, &access_key, &secret_key, &bucket).await;
+    let data = Data::init().await.expect("error initializing data")
+        .extract(&config.etl.url, config.etl.reference_below_rows).await.expect("error extracting data");
+
+    // cross-check polars before trusting its sessions, catches behaviour changes of an upgrade
+    let data = if cli.verify {
+        data.verify(session.length).await.expect("error verifying sessions")
+    } else {
+        data
+    };
+
+    let data = data
+        .transform(session.length, suspects).await.expect("error transforming data")
+        .load(&config.etl.data_path, &s3.region, &s3.endpoint, s3.access_key.as_ref(), s3.secret_key.as_ref(), &s3.bucket).await.expect("error loading data");
+
+    // a run without sessions loaded nothing useful, etl_sessions_created == 0 is what to alert on
+    if data.report.sessions_created == 0 {
+        warn!(rows_in = data.report.rows_in, "run produced zero sessions");
+    }
 
+    // failing to export metrics should not fail a run whose data was loaded
+    if let Some(path) = &config.etl.metrics_file {
+        if let Err(e) = data.report.write(path) {
+            error!(error = format!("{:#}", e), "error writing run metrics");
+        }
+    }
+    if let Some(url) = &config.etl.pushgateway_url {
+        if let Err(e) = data.report.push(url).await {
+            error!(error = format!("{:#}", e), "error pushing run metrics");
+        }
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,79 +1,185 @@
-use anyhow::{Result, Ok};
+use anyhow::{bail, ensure, Context, Result, Ok};
+use chrono::NaiveDateTime;
 use polars::{lazy::dsl::StrptimeOptions, prelude::*};
+use serde_json::{Map, Value};
 
+use std::collections::BTreeMap;
 use std::io::Cursor;
 use std::fs;
+use std::time::Instant;
+
+use tracing::{info, instrument, Span};
+
+use crate::report::RunReport;
+use crate::secrets::Secret;
+use webshop_config::SuspectSettings;
+use webshop_core::bucket::connect_to_bucket;
+use webshop_core::schema::frame_columns;
+
+// fields of an event with a column of their own, every other field ends up in `attributes`
+const EVENT_FIELDS: [&str; 3] = ["customer-id", "timestamp", "type"];
+
+// thresholds used to flag suspicious (bot/crawler) sessions
+#[derive(Debug, Clone)]
+pub struct SuspectThresholds {
+    // sessions with more events per minute than this are flagged
+    pub max_events_per_minute: f64,
+    // sessions whose median gap between events is shorter than this are flagged
+    pub min_median_gap_seconds: f64,
+    // sessions with more events than this are flagged
+    pub max_session_events: u32,
+}
 
-use s3::creds::Credentials;
-use s3::Bucket;
+impl Default for SuspectThresholds {
+    fn default() -> Self {
+        Self {
+            max_events_per_minute: 30.0,
+            min_median_gap_seconds: 1.0,
+            max_session_events: 1000,
+        }
+    }
+}
 
+impl From<&SuspectSettings> for SuspectThresholds {
+    fn from(settings: &SuspectSettings) -> Self {
+        Self {
+            max_events_per_minute: settings.max_events_per_minute,
+            min_median_gap_seconds: settings.min_median_gap_seconds,
+            max_session_events: settings.max_session_events,
+        }
+    }
+}
 
 // object for passing state around to all handlers
 pub struct Data {
     pub df: LazyFrame,
+    // the events of a small run, read and sessionized without polars instead of `df`
+    pub events: Option<Vec<SessionEvent>>,
+    // counts and stage durations of the run
+    pub report: RunReport,
 }
 
 impl Data {
     // initialize the empty state
     pub async fn init() -> Result<Self> {
-        println!("initializing...");
+        info!("initializing");
         Ok(Self {
             df: DataFrame::empty().lazy(),
+            events: None,
+            report: RunReport::default(),
         })
     }
 
-    // stage the data
-    pub async fn extract(mut self, url: &str) -> Result<Self> {
-        println!("retrieving the data and staging it...");
+    // stage the data, runs with fewer than `reference_below_rows` events skip polars until the load
+    #[instrument(name = "extract", skip_all, fields(rows = tracing::field::Empty))]
+    pub async fn extract(mut self, url: &str, reference_below_rows: Option<usize>) -> Result<Self> {
+        info!("retrieving the data and staging it");
+        let started = Instant::now();
 
         // read the body of the response from the requested url
         let body = reqwest::get(url.to_string())
             .await
-            .expect("error requesting")
+            .with_context(|| format!("error requesting {}", url))?
             .bytes()
-            .await?;
-        
-        // create a cursor
-        let file = Cursor::new(body);
-
-        // with the cursor, serialize the body of bytes into a Polars DataFrame/LazyFrame
-        let df = JsonReader::new(file)
-            .with_json_format(JsonFormat::JsonLines)
-            .infer_schema_len(Some(20))
-            .with_batch_size(100)
-            .finish()
-            .expect("error finishing dataframe")
-            .lazy();
-        
-        self.df = df;
+            .await
+            .with_context(|| format!("error reading the response of {}", url))?;
+
+        let rows = count_lines(&body);
+        Span::current().record("rows", rows);
+
+        if reference_below_rows.is_some_and(|limit| rows < limit) {
+            // a handful of events, reading them into the reference sessionizer beats planning a frame
+            let events = read_session_events(&body).expect("error reading events");
+            info!(rows, "extracted events for the reference sessionizer");
+
+            self.events = Some(events);
+        } else {
+            // serialize the body of bytes into a Polars DataFrame/LazyFrame
+            let df = read_events(&body).expect("error reading events");
+            info!(rows = df.height(), "extracted events");
+
+            self.df = df.lazy();
+        }
+
+        self.report.rows_in = rows;
+        self.report.stage("extract", started.elapsed());
         Ok(self)
     }
 
-    // initial sessionization for application state
-    pub async fn transform(mut self, session_length: u32) -> Result<Self> {
-        println!("transforming data by sessionizing it");
+    // check `sessionize` against the reference sessionizer on the extracted events, a run whose
+    // sessions differ fails before anything is loaded
+    #[instrument(name = "verify", skip_all, fields(session_length))]
+    pub async fn verify(mut self, session_length: u32) -> Result<Self> {
+        if self.events.is_some() {
+            info!("events are sessionized by the reference sessionizer, nothing to verify");
+            return Ok(self);
+        }
 
-        let ld = self.df;
+        info!("verifying the sessions against the reference sessionizer");
+        let started = Instant::now();
 
-        // sessionize the data
-        let df = sessionize(ld, session_length)
-            .await
-            .expect("error sessionizing");
+        let events = self.df.clone().collect()?;
+        let rows = verify_sessionize(&events, session_length).await?;
+        info!(rows, "sessions match the reference");
 
+        self.report.stage("verify", started.elapsed());
+        Ok(self)
+    }
+
+    // initial sessionization for application state, optionally flagging suspicious sessions
+    #[instrument(name = "transform", skip_all, fields(session_length, flag_suspects = suspects.is_some()))]
+    pub async fn transform(mut self, session_length: u32, suspects: Option<SuspectThresholds>) -> Result<Self> {
+        info!("transforming data by sessionizing it");
+        let started = Instant::now();
+
+        // sessionize the data, small runs without polars
+        let df = match self.events.take() {
+            Some(events) => sessionized_frame(&sessionize_reference(group_by_customer(events), session_length))
+                .expect("error sessionizing")
+                .lazy(),
+            None => sessionize(self.df, session_length)
+                .await
+                .expect("error sessionizing"),
+        };
+
+        // flag suspicious sessions, or mark everything as not suspect so the output schema stays the same
+        let df = match suspects {
+            Some(thresholds) => flag_suspects(df, &thresholds)
+                .await
+                .expect("error flagging suspects"),
+            None => df.with_column(lit(0).alias("is-suspect")),
+        };
+
+        // webshop.events keeps the time difference in whole minutes
+        let df = df.with_column(col("time-diff").cast(DataType::Int64));
+
+        // the columns of webshop.events in table order, the api copies the parquet file positionally
+        let df = df.select(frame_columns().iter().map(|c| col(c)).collect::<Vec<_>>());
+
+        // the frame is lazy, so this only covers building the plan, the work happens in load
+        self.report.stage("transform", started.elapsed());
         self.df = df;
         Ok(self)
     }
 
-    pub async fn load(self, data_path: &str, region: &str, endpoint: &str, access_key: &str, secret_key: &str, bucket: &str) {
-        println!("loading data");
+    // without an access and secret key the bucket credentials come from the provider chain
+    // (AWS_* env vars, the shared credentials profile or the instance metadata)
+    #[instrument(name = "load", skip_all, fields(bucket, rows = tracing::field::Empty))]
+    pub async fn load(mut self, data_path: &str, region: &str, endpoint: &str, access_key: Option<&Secret>, secret_key: Option<&Secret>, bucket: &str) -> Result<Self> {
+        info!("loading data");
+        let started = Instant::now();
 
+        let mut df = self.df.clone().collect().expect("error collecting data");
+        count_output(&mut self.report, &df)?;
 
         // shouldnt have to do this, should be able to convert a Polars DataFrame into a bytes representation or something
-        write_to_parquet(data_path, self.df.clone()).await;
+        write_to_parquet(data_path, &mut df).await;
         ///////////////////////////
 
         write_to_bucket(data_path, region, endpoint, access_key, secret_key, bucket).await;
 
+        self.report.stage("load", started.elapsed());
+        Ok(self)
     }
 
     // // re-sessionizing the data after initialization
@@ -91,12 +197,125 @@ impl Data {
     // }
 }
 
-async fn sessionize(lazydata: LazyFrame, session_length: u32) -> Result<LazyFrame> {
-    println!("sessionizing with a session length of {}", session_length);
+// read events in the JSON lines format of the source into a frame with the core fields and the
+// packed attributes, the input of `sessionize`
+pub fn read_events(body: &[u8]) -> Result<DataFrame> {
+    // create a cursor
+    let file = Cursor::new(body);
+
+    // with the cursor, serialize the body of bytes into a Polars DataFrame, inferring the schema
+    // from every line: a field only some events carry (the amount of an order) may first show
+    // up anywhere in the file and would otherwise be left out of the attributes
+    let df = JsonReader::new(file)
+        .with_json_format(JsonFormat::JsonLines)
+        .infer_schema_len(None)
+        .with_batch_size(100)
+        .finish()?;
+
+    // keep what the source sends beyond the core fields
+    pack_attributes(df, body)
+}
+
+// the events in the source, the non-empty lines of the body with their line number
+fn event_lines(body: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    body.split(|b| *b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|(i, line)| (i + 1, line))
+}
+
+fn count_lines(body: &[u8]) -> usize {
+    event_lines(body).count()
+}
+
+// the fields of an event of the source with the event struct unnested
+fn event_fields(line: &[u8]) -> Result<Map<String, Value>> {
+    let mut fields: Map<String, Value> = serde_json::from_slice(line)?;
+
+    if let Some(Value::Object(event)) = fields.remove("event") {
+        fields.extend(event);
+    }
+
+    Ok(fields)
+}
+
+// the attributes of an event, a json object of its fields without a column of their own. a field
+// the event doesn't carry is left out rather than set to null
+fn packed_attributes(mut fields: Map<String, Value>) -> String {
+    for field in EVENT_FIELDS {
+        fields.remove(field);
+    }
+
+    Value::Object(fields).to_string()
+}
+
+// read events in the JSON lines format of the source straight into the reference sessionizer,
+// without polars. like `session_events` it leaves out events without a customer id or with a
+// timestamp that doesn't parse, and packs the attributes like `read_events`
+pub fn read_session_events(body: &[u8]) -> Result<Vec<SessionEvent>> {
+    let mut events = Vec::new();
+
+    for (line_number, line) in event_lines(body) {
+        let fields = event_fields(line).with_context(|| format!("error parsing event on line {}", line_number))?;
+
+        let customer_id = fields.get("customer-id").and_then(Value::as_i64);
+        let timestamp = fields.get("timestamp").and_then(Value::as_str).and_then(parse_timestamp);
+        let event_type = fields.get("type").and_then(Value::as_str).map(String::from);
+
+        if let (Some(customer_id), Some(timestamp)) = (customer_id, timestamp) {
+            events.push(SessionEvent {
+                customer_id,
+                timestamp,
+                event_type,
+                attributes: packed_attributes(fields),
+            });
+        }
+    }
+
+    Ok(events)
+}
+
+// unnest the event struct and pack every field without a column of its own into a json
+// `attributes` column, one object per event (product ids, prices, page urls, ...)
+//
+// the objects are packed from the lines of the source rather than the frame, with the same code
+// as `read_session_events`, so both paths load the same attributes: polars would write a null
+// for every field some other event carries
+fn pack_attributes(df: DataFrame, body: &[u8]) -> Result<DataFrame> {
+    let df = df.unnest(["event"])?;
+
+    let extra: Vec<String> = df
+        .get_column_names()
+        .into_iter()
+        .filter(|c| !EVENT_FIELDS.contains(c))
+        .map(String::from)
+        .collect();
+    info!(?extra, "packing attributes");
+
+    let attributes = event_lines(body)
+        .map(|(line_number, line)| {
+            let fields = event_fields(line).with_context(|| format!("error parsing event on line {}", line_number))?;
+            Ok(packed_attributes(fields))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(attributes.len() == df.height(), "packed {} attributes for {} events", attributes.len(), df.height());
+
+    let mut packed = df.select(EVENT_FIELDS)?;
+    packed.with_column(Series::new("attributes", attributes))?;
+
+    Ok(packed)
+}
+
+// number the sessions of every customer: an event more than `session_length` minutes after the
+// previous event of the customer starts a new session, a gap of exactly `session_length` doesn't
+//
+// events without a customer id and events whose timestamp doesn't parse are dropped, they count
+// as rejected rows of the run. events of a customer with the same timestamp keep the order of the
+// input and are 0 minutes apart. `sessionize_reference` does the same without polars
+pub async fn sessionize(lazydata: LazyFrame, session_length: u32) -> Result<LazyFrame> {
+    info!(session_length, "sessionizing");
 
     let df = lazydata
-        // explode/normalize/unnest the event column which is a struct
-        .unnest(["event"])
         // remove null customer ids
         .filter(col("customer-id").is_not_null())
         // cast timestamp column from string to actual timestamp
@@ -109,12 +328,14 @@ async fn sessionize(lazydata: LazyFrame, session_length: u32) -> Result<LazyFram
                 cache: false,
             }  
         ))
-        // sort data frame by customer id asc, timestamp asc
+        // remove timestamps that didn't parse, they can't be placed in a session
+        .filter(col("timestamp").is_not_null())
+        // sort data frame by customer id asc, timestamp asc, ties keep the order of the input
         .sort_by_exprs(
             vec![col("customer-id"), col("timestamp")],
             vec![false, false],
             false,
-            false,
+            true,
         )
         // lag/shift the timestamp column by per customer
         .with_column(
@@ -124,13 +345,16 @@ async fn sessionize(lazydata: LazyFrame, session_length: u32) -> Result<LazyFram
                 .over([col("customer-id")])
                 .alias("prev-timestamp"),
         )
-        // calculate time difference between timestamp and lagged ts (converted from microsecs to min)
-        .with_column(((col("timestamp") - col("prev-timestamp")) / lit(6e7)).alias("time-diff"))
+        // calculate time difference between timestamp and lagged ts (converted from microsecs to min),
+        // as a float so a gap just over the session length isn't truncated to it
+        .with_column(
+            ((col("timestamp").cast(DataType::Int64) - col("prev-timestamp").cast(DataType::Int64)).cast(DataType::Float64) / lit(6e7))
+                .alias("time-diff"),
+        )
         // fill null time-diffs with 0s
-        .with_column(col("time-diff").fill_null(lit(0)))
-        // .filter(col("customer-id").eq(609))
+        .with_column(col("time-diff").fill_null(lit(0.0)))
         .with_columns([
-            when((col("time-diff")).gt(session_length))
+            when((col("time-diff")).gt(lit(session_length as f64)))
                 .then(1)
                 .otherwise(0)
                 .alias("new-session"),
@@ -151,44 +375,295 @@ async fn sessionize(lazydata: LazyFrame, session_length: u32) -> Result<LazyFram
             col("new-session"),
             col("session-number"),
             col("type"),
+            col("attributes"),
         ]);
 
     Ok(df)
 }
 
-async fn connect_to_bucket(region: &str, endpoint: &str, access_key: &str, secret_key: &str, bucket: &str) -> Result<Bucket> {
+// an event going into the reference sessionizer, the timestamp in microseconds since the epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionEvent {
+    pub customer_id: i64,
+    pub timestamp: i64,
+    pub event_type: Option<String>,
+    pub attributes: String,
+}
+
+// an event with its session, a row of the output of `sessionize`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionizedEvent {
+    pub customer_id: i64,
+    pub timestamp: i64,
+    pub time_diff: f64,
+    pub new_session: i64,
+    pub session_number: i64,
+    pub event_type: Option<String>,
+    pub attributes: String,
+}
+
+// the events of a frame from `read_events` for the reference sessionizer, without the events
+// `sessionize` drops: no customer id or a timestamp that doesn't parse
+pub fn session_events(df: &DataFrame) -> Result<Vec<SessionEvent>> {
+    let customer_id = df.column("customer-id")?.cast(&DataType::Int64)?;
+    let timestamp = df.column("timestamp")?.utf8()?;
+    let event_type = df.column("type")?.utf8()?;
+    let attributes = df.column("attributes")?.utf8()?;
+
+    let events = customer_id
+        .i64()?
+        .into_iter()
+        .zip(timestamp)
+        .zip(event_type)
+        .zip(attributes)
+        .filter_map(|(((customer_id, timestamp), event_type), attributes)| {
+            Some(SessionEvent {
+                customer_id: customer_id?,
+                timestamp: parse_timestamp(timestamp?)?,
+                event_type: event_type.map(String::from),
+                attributes: attributes.unwrap_or("{}").to_string(),
+            })
+        })
+        .collect();
+
+    Ok(events)
+}
+
+// microseconds since the epoch of a timestamp in the format `sessionize` parses
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|t| t.timestamp_micros())
+}
+
+// group events by customer in customer id order, the events of a customer keep the order of the input
+pub fn group_by_customer(events: impl IntoIterator<Item = SessionEvent>) -> Vec<Vec<SessionEvent>> {
+    let mut customers: BTreeMap<i64, Vec<SessionEvent>> = BTreeMap::new();
+
+    for event in events {
+        customers.entry(event.customer_id).or_default().push(event);
+    }
+
+    customers.into_values().collect()
+}
+
+// the sessions of `sessionize` in plain rust, for events grouped by customer with every group
+// holding all the events of one customer. a row at a time and without polars, so it doubles as
+// the oracle `verify_sessionize` checks polars against and as a path for small inputs
+pub fn sessionize_reference<I>(customers: I, session_length: u32) -> Vec<SessionizedEvent>
+where
+    I: IntoIterator<Item = Vec<SessionEvent>>,
+{
+    let mut sessionized = Vec::new();
+
+    for mut events in customers {
+        // a stable sort, ties keep the order of the input
+        events.sort_by_key(|e| e.timestamp);
+
+        let mut previous: Option<i64> = None;
+        let mut session_number = 0;
+
+        for event in events {
+            // same arithmetic as `sessionize` so the minutes compare exactly
+            let time_diff = previous.map_or(0.0, |p| (event.timestamp - p) as f64 / 6e7);
+            let new_session = i64::from(time_diff > session_length as f64);
+            session_number += new_session;
+            previous = Some(event.timestamp);
+
+            sessionized.push(SessionizedEvent {
+                customer_id: event.customer_id,
+                timestamp: event.timestamp,
+                time_diff,
+                new_session,
+                session_number,
+                event_type: event.event_type,
+                attributes: event.attributes,
+            });
+        }
+    }
+
+    sessionized
+}
+
+// the rows of a collected `sessionize` frame
+pub fn sessionized_events(df: &DataFrame) -> Result<Vec<SessionizedEvent>> {
+    let customer_id = df.column("customer-id")?.cast(&DataType::Int64)?;
+    let timestamp = df.column("timestamp")?.cast(&DataType::Int64)?;
+    let time_diff = df.column("time-diff")?.cast(&DataType::Float64)?;
+    let new_session = df.column("new-session")?.cast(&DataType::Int64)?;
+    let session_number = df.column("session-number")?.cast(&DataType::Int64)?;
+    let event_type = df.column("type")?.utf8()?;
+    let attributes = df.column("attributes")?.utf8()?;
+
+    let (customer_id, timestamp, time_diff) = (customer_id.i64()?, timestamp.i64()?, time_diff.f64()?);
+    let (new_session, session_number) = (new_session.i64()?, session_number.i64()?);
+
+    (0..df.height())
+        .map(|i| {
+            Some(SessionizedEvent {
+                customer_id: customer_id.get(i)?,
+                timestamp: timestamp.get(i)?,
+                time_diff: time_diff.get(i)?,
+                new_session: new_session.get(i)?,
+                session_number: session_number.get(i)?,
+                event_type: event_type.get(i).map(String::from),
+                attributes: attributes.get(i)?.to_string(),
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .context("sessionized events with a null column")
+}
+
+// the rows of the reference sessionizer as a frame with the columns of `sessionize`, for the
+// suspect flagging and the load
+pub fn sessionized_frame(events: &[SessionizedEvent]) -> Result<DataFrame> {
+    let timestamp: Vec<i64> = events.iter().map(|e| e.timestamp).collect();
+    let event_type: Vec<Option<&str>> = events.iter().map(|e| e.event_type.as_deref()).collect();
+    let attributes: Vec<&str> = events.iter().map(|e| e.attributes.as_str()).collect();
+
+    let df = DataFrame::new(vec![
+        Series::new("customer-id", events.iter().map(|e| e.customer_id).collect::<Vec<_>>()),
+        Series::new("timestamp", timestamp).cast(&DataType::Datetime(TimeUnit::Microseconds, None))?,
+        Series::new("time-diff", events.iter().map(|e| e.time_diff).collect::<Vec<_>>()),
+        Series::new("new-session", events.iter().map(|e| e.new_session).collect::<Vec<_>>()),
+        Series::new("session-number", events.iter().map(|e| e.session_number).collect::<Vec<_>>()),
+        Series::new("type", event_type),
+        Series::new("attributes", attributes),
+    ])?;
+
+    Ok(df)
+}
+
+// run `sessionize` and the reference sessionizer on the same events and compare them row by row,
+// returns the number of rows compared or the first row that differs
+pub async fn verify_sessionize(events: &DataFrame, session_length: u32) -> Result<usize> {
+    let df = sessionize(events.clone().lazy(), session_length).await?.collect()?;
 
-    let bucket = Bucket::new(
-        bucket,
-        s3::Region::Custom {
-            region: region.to_owned(),
-            endpoint: endpoint.to_owned(),
-        },
-        get_credentials(Some(access_key), Some(secret_key)).await.expect("error with creds"),
-    ).expect("error making bucket")
-    .with_path_style();
+    let actual = sessionized_events(&df)?;
+    let expected = sessionize_reference(group_by_customer(session_events(events)?), session_length);
 
-    Ok(bucket)
+    if let Some(i) = (0..actual.len().min(expected.len())).find(|&i| actual[i] != expected[i]) {
+        bail!("sessionize differs from the reference at row {}: {:?}, expected {:?}", i, actual[i], expected[i]);
+    }
+    ensure!(actual.len() == expected.len(), "sessionize returned {} rows, the reference {}", actual.len(), expected.len());
+
+    Ok(actual.len())
 }
 
-async fn get_credentials(access_key: Option<&str>, secret_key: Option<&str>) -> Result<Credentials> {
-    let creds = s3::creds::Credentials::new(
-        access_key, secret_key, None, None, None);
+// flag customers with sessions that look like bot or crawler traffic
+async fn flag_suspects(lazydata: LazyFrame, thresholds: &SuspectThresholds) -> Result<LazyFrame> {
+    info!(?thresholds, "flagging suspects");
+
+    let session = [col("customer-id"), col("session-number")];
 
-    Ok(creds.expect("error constructing creds"))
+    let df = lazydata
+        // gap to the previous event of the customer in seconds (null for the first event)
+        .with_column(
+            ((col("timestamp") - col("timestamp").shift(1))
+                .over([col("customer-id")])
+                .cast(DataType::Int64)
+                .cast(DataType::Float64)
+                / lit(1e6))
+            .alias("gap-seconds"),
+        )
+        // per session: number of events, duration in minutes and median gap between events
+        .with_columns([
+            col("timestamp")
+                .count()
+                .over(session.clone())
+                .cast(DataType::Float64)
+                .alias("session-events"),
+            ((col("timestamp").max() - col("timestamp").min())
+                .over(session.clone())
+                .cast(DataType::Int64)
+                .cast(DataType::Float64)
+                / lit(6e7))
+            .alias("session-minutes"),
+            // the first event of a session carries the gap to the previous session, ignore it
+            when(col("new-session").eq(lit(1)))
+                .then(lit(NULL))
+                .otherwise(col("gap-seconds"))
+                .median()
+                .over(session.clone())
+                .alias("median-gap-seconds"),
+        ])
+        // sessions shorter than a minute are rated as if they lasted a minute
+        .with_column(
+            (col("session-events")
+                / when(col("session-minutes").lt(lit(1.0)))
+                    .then(lit(1.0))
+                    .otherwise(col("session-minutes")))
+            .alias("events-per-minute"),
+        )
+        .with_column(
+            when(
+                col("events-per-minute")
+                    .gt(lit(thresholds.max_events_per_minute))
+                    .or(col("median-gap-seconds").lt(lit(thresholds.min_median_gap_seconds)))
+                    .or(col("session-events").gt(lit(thresholds.max_session_events as f64))),
+            )
+            .then(1)
+            .otherwise(0)
+            .alias("suspect-session"),
+        )
+        // a customer with any suspect session is flagged as a whole so per customer metrics are not skewed
+        .with_column(
+            col("suspect-session")
+                .max()
+                .over([col("customer-id")])
+                .alias("is-suspect"),
+        )
+        // keep only necessary columns
+        .select([
+            col("customer-id"),
+            col("timestamp"),
+            col("time-diff"),
+            col("new-session"),
+            col("session-number"),
+            col("type"),
+            col("is-suspect"),
+            col("attributes"),
+        ]);
+
+    Ok(df)
+}
+
+// rows, sessions and suspect rows of the transformed data
+fn count_output(report: &mut RunReport, df: &DataFrame) -> Result<()> {
+    report.rows_out = df.height();
+    report.sessions_created = df
+        .select(["customer-id", "session-number"])?
+        .unique(None, UniqueKeepStrategy::Any, None)?
+        .height();
+    report.suspect_rows = df.column("is-suspect")?.cast(&DataType::Int64)?.i64()?.sum().unwrap_or(0) as usize;
+
+    info!(
+        rows_in = report.rows_in,
+        rows_out = report.rows_out,
+        rows_rejected = report.rows_rejected(),
+        sessions = report.sessions_created,
+        "counted output"
+    );
+
+    Ok(())
 }
 
-async fn write_to_parquet(path: &str, df: LazyFrame) {
+async fn write_to_parquet(path: &str, df: &mut DataFrame) {
     let mut file = std::fs::File::create(path).expect("error create file path for parquet");
-    ParquetWriter::new(&mut file).finish(&mut df.collect().expect("error to parquet")).expect("another error making parquet writer");
+
+    Span::current().record("rows", df.height());
+    info!(rows = df.height(), path, "writing parquet");
+
+    ParquetWriter::new(&mut file).finish(df).expect("another error making parquet writer");
 }
 
-async fn write_to_bucket(path: &str, region: &str, endpoint: &str, access_key: &str, secret_key: &str, bucket: &str) {
+async fn write_to_bucket(path: &str, region: &str, endpoint: &str, access_key: Option<&Secret>, secret_key: Option<&Secret>, bucket: &str) {
     let bucket = connect_to_bucket(region, endpoint, access_key, secret_key, bucket).await.expect("error instantiating bucket");
 
     let bytes = fs::read(path).expect("error reading file path");
 
     // add file to bucket
+    info!(bytes = bytes.len(), "uploading to bucket");
     bucket.put_object(path, &bytes).await.expect("error putting object");
 
     fs::remove_file(path).expect("error removing file");
@@ -1 +1,3 @@
-pub mod etl;
\ No newline at end of file
+pub mod etl;
+pub mod report;
+pub use webshop_config::secrets;
\ No newline at end of file
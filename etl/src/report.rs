@@ -0,0 +1,88 @@
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+// counts and timings of one etl run, exported in the prometheus text format
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    // events read from the source
+    pub rows_in: usize,
+    // events written to the bucket
+    pub rows_out: usize,
+    // sessions (distinct customer and session number) in the output
+    pub sessions_created: usize,
+    // events of customers flagged as suspect
+    pub suspect_rows: usize,
+    // duration of every stage that ran, in the order they ran
+    pub stages: Vec<(&'static str, Duration)>,
+}
+
+impl RunReport {
+    // events dropped by the transform, e.g. without a customer id
+    pub fn rows_rejected(&self) -> usize {
+        self.rows_in.saturating_sub(self.rows_out)
+    }
+
+    pub fn stage(&mut self, name: &'static str, duration: Duration) {
+        self.stages.push((name, duration));
+    }
+
+    // the report in the prometheus text exposition format, as read by the node exporter
+    // textfile collector and accepted by the pushgateway
+    pub fn render(&self) -> String {
+        let finished = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut out = String::new();
+
+        gauge(&mut out, "etl_rows_in", "Events read from the source", self.rows_in as f64);
+        gauge(&mut out, "etl_rows_out", "Events written to the staging bucket", self.rows_out as f64);
+        gauge(&mut out, "etl_rows_rejected", "Events dropped by the transform", self.rows_rejected() as f64);
+        gauge(&mut out, "etl_sessions_created", "Sessions in the output of the run", self.sessions_created as f64);
+        gauge(&mut out, "etl_suspect_rows", "Events of customers flagged as suspect", self.suspect_rows as f64);
+
+        writeln!(out, "# HELP etl_stage_duration_seconds Duration of the etl stages").unwrap();
+        writeln!(out, "# TYPE etl_stage_duration_seconds gauge").unwrap();
+        for (stage, duration) in &self.stages {
+            writeln!(out, "etl_stage_duration_seconds{{stage=\"{}\"}} {}", stage, duration.as_secs_f64()).unwrap();
+        }
+
+        gauge(&mut out, "etl_last_run_timestamp_seconds", "Unix time the last run finished", finished.as_secs_f64());
+
+        out
+    }
+
+    // write the report atomically so the textfile collector never reads a partial file
+    pub fn write(&self, path: &str) -> Result<()> {
+        let tmp = format!("{path}.tmp");
+
+        fs::write(&tmp, self.render()).with_context(|| format!("error writing metrics to {}", tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("error moving metrics to {}", path))?;
+
+        info!(path, "wrote run metrics");
+        Ok(())
+    }
+
+    // replace the metrics of the etl job on a pushgateway
+    pub async fn push(&self, url: &str) -> Result<()> {
+        let url = format!("{}/metrics/job/etl", url.trim_end_matches('/'));
+
+        reqwest::Client::new()
+            .put(&url)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(self.render())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("error pushing metrics to {}", url))?;
+
+        info!(url, "pushed run metrics");
+        Ok(())
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} gauge").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
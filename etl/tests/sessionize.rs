@@ -0,0 +1,226 @@
+// tests of `sessionize` against golden fixtures and property based invariants
+//
+// every fixture in tests/fixtures is a `<name>.jsonl` file in the format of the source and a
+// `<name>.expected.jsonl` file with the sessionized rows, sorted by customer and timestamp
+//
+// the expected rows are jsonl rather than the parquet the transform writes, so they can be read
+// and reviewed in a diff. they're the output of `sessionize`: on the way to parquet the transform
+// only truncates time-diff to the whole minutes of webshop.events
+
+use std::fs;
+use std::path::PathBuf;
+
+use polars::prelude::*;
+use proptest::prelude::*;
+use serde::Deserialize;
+
+use etl::etl::{group_by_customer, read_events, read_session_events, sessionize, sessionize_reference, sessionized_events, sessionized_frame, verify_sessionize};
+
+const SESSION_LENGTH: u32 = 30;
+
+// a sessionized event, as in the expected fixtures
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Row {
+    #[serde(rename = "customer-id")]
+    customer_id: i64,
+    // %Y-%m-%dT%H:%M:%S
+    timestamp: String,
+    #[serde(rename = "time-diff")]
+    time_diff: f64,
+    #[serde(rename = "new-session")]
+    new_session: i64,
+    #[serde(rename = "session-number")]
+    session_number: i64,
+    #[serde(rename = "type")]
+    event_type: String,
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+// read the events and sessionize them into rows
+async fn sessionized(input: &[u8], session_length: u32) -> Vec<Row> {
+    let events = read_events(input).expect("error reading events");
+    let df = sessionize(events.lazy(), session_length)
+        .await
+        .expect("error sessionizing")
+        .select([
+            col("customer-id").cast(DataType::Int64),
+            col("timestamp").dt().to_string("%Y-%m-%dT%H:%M:%S"),
+            col("time-diff").cast(DataType::Float64),
+            col("new-session").cast(DataType::Int64),
+            col("session-number").cast(DataType::Int64),
+            col("type"),
+        ])
+        .collect()
+        .expect("error collecting sessionized events");
+
+    let customer_id = df.column("customer-id").unwrap().i64().unwrap();
+    let timestamp = df.column("timestamp").unwrap().utf8().unwrap();
+    let time_diff = df.column("time-diff").unwrap().f64().unwrap();
+    let new_session = df.column("new-session").unwrap().i64().unwrap();
+    let session_number = df.column("session-number").unwrap().i64().unwrap();
+    let event_type = df.column("type").unwrap().utf8().unwrap();
+
+    (0..df.height())
+        .map(|i| Row {
+            customer_id: customer_id.get(i).unwrap(),
+            timestamp: timestamp.get(i).unwrap().to_string(),
+            time_diff: time_diff.get(i).unwrap(),
+            new_session: new_session.get(i).unwrap(),
+            session_number: session_number.get(i).unwrap(),
+            event_type: event_type.get(i).unwrap().to_string(),
+        })
+        .collect()
+}
+
+async fn check_fixture(name: &str) {
+    let input = fs::read(fixture(&format!("{name}.jsonl"))).expect("error reading fixture");
+    let expected: Vec<Row> = fs::read_to_string(fixture(&format!("{name}.expected.jsonl")))
+        .expect("error reading expected output")
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).expect("error parsing expected row"))
+        .collect();
+
+    assert_eq!(sessionized(&input, SESSION_LENGTH).await, expected, "fixture {name}");
+}
+
+// events of a customer at the same time keep the order of the input
+#[tokio::test]
+async fn ties_on_timestamp() {
+    check_fixture("ties").await;
+}
+
+// a gap of exactly the session length stays in the session, anything longer starts a new one
+#[tokio::test]
+async fn gap_equal_to_session_length() {
+    check_fixture("exact_gap").await;
+}
+
+#[tokio::test]
+async fn unsorted_input() {
+    check_fixture("unsorted").await;
+}
+
+// events without a customer are dropped
+#[tokio::test]
+async fn null_customers() {
+    check_fixture("null_customers").await;
+}
+
+#[tokio::test]
+async fn single_event_per_customer() {
+    check_fixture("single_event").await;
+}
+
+// events whose timestamp doesn't parse are dropped, the gap is taken to the previous valid event
+#[tokio::test]
+async fn malformed_timestamps() {
+    check_fixture("malformed_timestamps").await;
+}
+
+// events as (customer, seconds after midnight, type) in the format of the source
+fn source(events: &[(i64, u32, &str)]) -> Vec<u8> {
+    events
+        .iter()
+        .enumerate()
+        .map(|(id, (customer_id, seconds, event_type))| {
+            format!(
+                "{{\"id\": {id}, \"type\": \"{event_type}\", \"event\": {{\"customer-id\": {customer_id}, \"timestamp\": \"2023-01-01T{:02}:{:02}:{:02}.000000\"}}}}\n",
+                seconds / 3600,
+                seconds % 3600 / 60,
+                seconds % 60
+            )
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+// seconds after midnight of a %Y-%m-%dT%H:%M:%S timestamp on the first day
+fn seconds(timestamp: &str) -> i64 {
+    let time: Vec<i64> = timestamp[11..].split(':').map(|part| part.parse().unwrap()).collect();
+
+    time[0] * 3600 + time[1] * 60 + time[2]
+}
+
+fn events() -> impl Strategy<Value = Vec<(i64, u32, &'static str)>> {
+    prop::collection::vec((1i64..6, 0u32..6 * 3600, prop::sample::select(vec!["page_view", "add_to_cart", "placed_order"])), 1..80)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    // every event is kept, sessions are numbered from 0 per customer and only go up, by exactly one
+    // for every event that is more than the session length after the previous event of the customer
+    #[test]
+    fn sessions_follow_the_gaps(events in events(), session_length in 1u32..90) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let rows = runtime.block_on(sessionized(&source(&events), session_length));
+
+        prop_assert_eq!(rows.len(), events.len());
+
+        for (i, row) in rows.iter().enumerate() {
+            let previous = i.checked_sub(1).map(|p| &rows[p]).filter(|p| p.customer_id == row.customer_id);
+
+            match previous {
+                None => {
+                    prop_assert_eq!(row.time_diff, 0.0);
+                    prop_assert_eq!(row.new_session, 0);
+                    prop_assert_eq!(row.session_number, 0);
+                }
+                Some(previous) => {
+                    let gap_minutes = (seconds(&row.timestamp) - seconds(&previous.timestamp)) as f64 / 60.0;
+
+                    prop_assert!(gap_minutes >= 0.0, "events of a customer are sorted by timestamp");
+                    prop_assert!((row.time_diff - gap_minutes).abs() < 1e-9);
+                    prop_assert_eq!(row.new_session == 1, gap_minutes > session_length as f64);
+                    prop_assert_eq!(row.session_number, previous.session_number + row.new_session);
+                }
+            }
+        }
+
+        // customers are sorted too, so every customer is one run of rows
+        prop_assert!(rows.windows(2).all(|pair| pair[0].customer_id <= pair[1].customer_id));
+    }
+
+    // the reference sessionizer agrees with polars row by row
+    #[test]
+    fn reference_matches_polars(events in events(), session_length in 1u32..90) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let events = read_events(&source(&events)).unwrap();
+
+        prop_assert_eq!(runtime.block_on(verify_sessionize(&events, session_length)).unwrap(), events.height());
+    }
+}
+
+// the fixtures cover the edge cases the random events rarely hit
+#[tokio::test]
+async fn reference_matches_polars_on_fixtures() {
+    for name in ["ties", "exact_gap", "unsorted", "null_customers", "single_event", "malformed_timestamps"] {
+        let input = fs::read(fixture(&format!("{name}.jsonl"))).expect("error reading fixture");
+        let events = read_events(&input).expect("error reading events");
+
+        verify_sessionize(&events, SESSION_LENGTH).await.unwrap_or_else(|e| panic!("fixture {name}: {e:#}"));
+    }
+}
+
+// the polars-free path of small runs sessionizes and packs the fixtures like polars
+#[tokio::test]
+async fn reference_path_matches_polars_on_fixtures() {
+    for name in ["ties", "exact_gap", "unsorted", "null_customers", "single_event", "malformed_timestamps"] {
+        let input = fs::read(fixture(&format!("{name}.jsonl"))).expect("error reading fixture");
+
+        let events = read_session_events(&input).expect("error reading session events");
+        let frame = sessionized_frame(&sessionize_reference(group_by_customer(events), SESSION_LENGTH)).expect("error building frame");
+
+        let polars = sessionize(read_events(&input).expect("error reading events").lazy(), SESSION_LENGTH)
+            .await
+            .expect("error sessionizing")
+            .collect()
+            .expect("error collecting sessionized events");
+
+        assert_eq!(sessionized_events(&frame).unwrap(), sessionized_events(&polars).unwrap(), "fixture {name}");
+    }
+}